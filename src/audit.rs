@@ -0,0 +1,92 @@
+use anyhow::{Context, Result};
+use log::warn;
+use serde::Serialize;
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    sync::Mutex,
+};
+use tokio::sync::broadcast;
+
+use crate::config::AuditConfig;
+
+/// Connection events recorded in the audit log
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEvent {
+    Connect,
+    Disconnect,
+    AuthFailure,
+}
+
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    timestamp: String,
+    event: AuditEvent,
+    tunnel: &'a str,
+    gate: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<&'a str>,
+}
+
+/// Append-only JSON Lines log of connection events for compliance retention.
+///
+/// Each event is written as one line and flushed immediately. Write failures
+/// are logged and otherwise ignored so auditing can never take a tunnel down.
+/// Events are also published to the live feed whether or not a file is set.
+pub struct AuditLog {
+    file: Option<Mutex<File>>,
+    events: broadcast::Sender<String>,
+}
+
+impl AuditLog {
+    pub fn new(config: Option<&AuditConfig>, events: broadcast::Sender<String>) -> Result<Self> {
+        let file = match config {
+            Some(config) => Some(Mutex::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&config.path)
+                    .with_context(|| {
+                        format!("Failed to open audit log {}", config.path.display())
+                    })?,
+            )),
+            None => None,
+        };
+
+        Ok(Self { file, events })
+    }
+
+    pub fn record(&self, event: AuditEvent, tunnel: &str, gate: &str, detail: Option<&str>) {
+        if self.file.is_none() && self.events.receiver_count() == 0 {
+            return;
+        }
+
+        let record = AuditRecord {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            event,
+            tunnel,
+            gate,
+            detail,
+        };
+
+        let mut line = match serde_json::to_string(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("Failed to encode audit record: {}", e);
+                return;
+            }
+        };
+        let _ = self.events.send(line.clone());
+
+        let Some(file) = &self.file else {
+            return;
+        };
+        line.push('\n');
+
+        let mut file = file.lock().unwrap();
+        if let Err(e) = file.write_all(line.as_bytes()).and_then(|_| file.flush()) {
+            warn!("Failed to write audit record: {}", e);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use anyhow::Result;
+
+use crate::metrics::TunnelStatus;
+
+/// Default location of the control socket queried by `m-tunnel status`
+pub const DEFAULT_CONTROL_SOCKET: &str = "/run/m-tunnel.sock";
+
+/// Each tunnel's status as reported by the instance serving the control
+/// socket at `path`, sorted by name. `None` for a status this build doesn't know.
+#[cfg(unix)]
+pub async fn tunnel_statuses(path: &str) -> Result<Vec<(String, Option<TunnelStatus>)>> {
+    use tokio::io::AsyncReadExt;
+
+    let mut stream = request(path, "/metrics.json").await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| anyhow::anyhow!("Malformed response from {}", path))?;
+    check_status(path, head.lines().next().unwrap_or_default())?;
+
+    let summary: serde_json::Value = serde_json::from_str(body)
+        .map_err(|e| anyhow::anyhow!("Malformed response from {}: {}", path, e))?;
+    let mut tunnels: Vec<(String, Option<TunnelStatus>)> = summary["tunnels"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(name, stats)| {
+            let status = serde_json::from_value(stats["status"].clone()).ok();
+            (name.clone(), status)
+        })
+        .collect();
+    tunnels.sort_by(|a, b| a.0.cmp(&b.0));
+
+    Ok(tunnels)
+}
+
+#[cfg(not(unix))]
+pub async fn tunnel_statuses(_path: &str) -> Result<Vec<(String, Option<TunnelStatus>)>> {
+    Err(anyhow::anyhow!(
+        "m-tunnel status needs a Unix control socket"
+    ))
+}
+
+/// Follow the live event feed of the instance serving the control socket at
+/// `path`, passing each JSON event line to `on_event` until the instance exits
+#[cfg(unix)]
+pub async fn follow_events(path: &str, mut on_event: impl FnMut(&str)) -> Result<()> {
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    let mut lines = BufReader::new(request(path, "/events").await?).lines();
+
+    check_status(path, &lines.next_line().await?.unwrap_or_default())?;
+    while let Some(header) = lines.next_line().await? {
+        if header.trim().is_empty() {
+            break;
+        }
+    }
+
+    while let Some(event) = lines.next_line().await? {
+        on_event(&event);
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub async fn follow_events(_path: &str, _on_event: impl FnMut(&str)) -> Result<()> {
+    Err(anyhow::anyhow!(
+        "m-tunnel events needs a Unix control socket"
+    ))
+}
+
+/// Send a GET for `uri` over the control socket. HTTP/1.0 keeps streamed
+/// bodies unchunked and has the server close the connection when done.
+#[cfg(unix)]
+async fn request(path: &str, uri: &str) -> Result<tokio::net::UnixStream> {
+    use anyhow::Context;
+    use tokio::io::AsyncWriteExt;
+
+    let mut stream = tokio::net::UnixStream::connect(path)
+        .await
+        .with_context(|| format!("Failed to connect to control socket {}", path))?;
+    stream
+        .write_all(format!("GET {} HTTP/1.0\r\nHost: localhost\r\n\r\n", uri).as_bytes())
+        .await?;
+    Ok(stream)
+}
+
+#[cfg(unix)]
+fn check_status(path: &str, status_line: &str) -> Result<()> {
+    if status_line.starts_with("HTTP/1.1 200") || status_line.starts_with("HTTP/1.0 200") {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "Unexpected response from {}: {}",
+            path,
+            status_line.trim()
+        ))
+    }
+}
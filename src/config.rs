@@ -1,34 +1,193 @@
 use anyhow::{anyhow, Context, Result};
 use serde::{Deserialize, Serialize};
-use std::{fs, path::PathBuf};
+use std::{collections::HashMap, fs, net::IpAddr, path::Path, path::PathBuf};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
-    pub gate: SshConfig,
-    pub tunnels: Vec<TunnelConfig>,
+    #[serde(default)]
+    pub gate: Option<SshConfig>,
+    #[serde(default)]
+    pub gates: Vec<SshConfig>, // Weighted round-robin pool, takes precedence over `gate`
+    #[serde(default)]
+    pub tunnels: Vec<TunnelConfig>, // When empty, read from a legacy tunnels.conf next to the config
     pub limits: ConnectionLimits,
+    #[serde(default)]
+    pub audit: Option<AuditConfig>,
+    #[serde(default)]
+    pub statsd: Option<StatsdConfig>,
+    #[serde(default)]
+    pub heartbeat: Option<HeartbeatConfig>,
+    #[serde(default)]
+    pub health: HealthConfig,
+    #[serde(default)]
+    pub metrics_tls: Option<MetricsTlsConfig>, // Serve the metrics port over HTTPS
+    #[serde(default)]
+    pub control_socket: Option<PathBuf>, // Serve metrics and control API on this Unix socket
+    #[serde(default)]
+    pub config_url: Option<String>, // JSON endpoint serving {"tunnels": [...]}, merged over local tunnels
+    #[serde(default = "default_config_url_cache")]
+    pub config_url_cache: PathBuf, // Last good response from config_url
+}
+
+fn default_config_url_cache() -> PathBuf {
+    PathBuf::from("./tunnels.cache.json")
+}
+
+/// Body served by `config_url`
+#[derive(Debug, Serialize, Deserialize)]
+struct RemoteTunnels {
+    tunnels: Vec<TunnelConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SshConfig {
+    #[serde(default)]
+    pub name: Option<String>, // Name tunnels use to select this gate
     pub host: String,
+    #[serde(default)]
     pub user: String,
+    #[serde(default = "default_ssh_port")]
     pub port: u16,
+    #[serde(default)]
     pub key_path: PathBuf,
+    #[serde(default)]
+    pub auth_method: AuthMethod, // key_file authenticates with key_path, agent with ssh-agent's keys
     pub timeout: u64,
     pub keepalive_interval: u64,
     pub server_name: Option<String>, // Display name for the server
+    #[serde(default = "default_gate_weight")]
+    pub weight: u32, // Share of connection attempts when several gates are configured
+    #[serde(default)]
+    pub use_ssh_config: bool, // Treat host as a ~/.ssh/config alias and let it supply the options
+    #[serde(default)]
+    pub banner_pattern: Option<String>, // Regex the gate's login banner must match
+    #[serde(default)]
+    pub cert_path: Option<PathBuf>, // OpenSSH certificate presented together with key_path
+    #[serde(default, skip_serializing)]
+    pub key_passphrase: Option<String>, // Passphrase of an encrypted key_path; never served by /config
+    #[serde(default)]
+    pub key_passphrase_env: Option<String>, // Environment variable holding the passphrase instead
+    #[serde(default)]
+    pub known_hosts_path: Option<PathBuf>, // Verify host keys against this file instead of ~/.ssh/known_hosts
+    #[serde(default)]
+    pub host_key_policy: Option<HostKeyPolicy>, // Default: accept_new with known_hosts_path, off without
+    #[serde(default)]
+    pub host_key_fingerprint: Option<String>, // Only accept the host key with this SHA256 fingerprint
+    #[serde(default)]
+    pub ssh_verbose: bool, // Run ssh with -v, logging its output at debug level and reporting the server version
+    #[serde(default)]
+    pub pin_gate_ip: bool, // Resolve host once at startup and connect to that address for the whole run
+    #[serde(default)]
+    pub preflight_checks: bool, // Run a test command on the gate at startup to detect forced-command accounts
+    #[serde(default)]
+    pub jump_host: Option<String>, // Reach the gate through this bastion ([user@]host[:port]), passed to ssh -J
+    #[serde(skip)]
+    pub pinned_ip: Option<IpAddr>, // Address resolved for pin_gate_ip
+    #[serde(skip)]
+    pub pinned_known_hosts: Option<PathBuf>, // Generated file holding the key matching host_key_fingerprint
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// How ssh authenticates to a gate
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    #[default]
+    KeyFile, // The private key at key_path
+    Agent, // Keys held by ssh-agent (SSH_AUTH_SOCK); no key file needed
+}
+
+/// How ssh checks a gate's host key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HostKeyPolicy {
+    AcceptNew, // Record an unknown gate's key on first connect, reject a changed key
+    Strict,    // Only connect to gates whose key is already listed
+    Off,       // No checking at all
+}
+
+fn default_gate_weight() -> u32 {
+    1
+}
+
+fn default_ssh_port() -> u16 {
+    22
+}
+
+impl SshConfig {
+    /// Identifier used for this gate in metrics
+    pub fn label(&self) -> String {
+        self.server_name
+            .clone()
+            .unwrap_or_else(|| self.host.clone())
+    }
+
+    /// Passphrase for an encrypted key, from the config or its environment variable
+    pub fn key_passphrase(&self) -> Option<String> {
+        self.key_passphrase.clone().or_else(|| {
+            self.key_passphrase_env
+                .as_deref()
+                .and_then(|var| std::env::var(var).ok())
+        })
+    }
+
+    /// The host key policy in effect. Gates without one keep the old
+    /// behaviour: trust on first use with known_hosts_path, no checking without.
+    pub fn host_key_checking(&self) -> HostKeyPolicy {
+        if self.host_key_fingerprint.is_some() {
+            return HostKeyPolicy::Strict;
+        }
+        match (self.host_key_policy, &self.known_hosts_path) {
+            (Some(policy), _) => policy,
+            (None, Some(_)) => HostKeyPolicy::AcceptNew,
+            (None, None) => HostKeyPolicy::Off,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TunnelConfig {
     pub name: String,
     pub direction: String,
     pub local_host: String,
     pub local_port: u16,
-    pub remote_host: String,
-    pub remote_port: u16,
+    #[serde(default)]
+    pub remote_host: String, // Forward target; unused by dynamic tunnels
+    #[serde(default)]
+    pub remote_port: u16, // Forward target port; unused by dynamic tunnels
     pub enabled: bool,
+    #[serde(default)]
+    pub require_local_service: bool, // Send tunnels: hold the forward back until the local service accepts connections
+    #[serde(default)]
+    pub group: Option<String>, // Tag for bulk restart/pause/resume through the control API
+    #[serde(default)]
+    pub degraded_reconnects: Option<u32>, // Flag the tunnel degraded after this many reconnects within degraded_window_secs
+    #[serde(default = "default_degraded_window_secs")]
+    pub degraded_window_secs: u64,
+    #[serde(default)]
+    pub degraded_fails_health: bool, // Make /health answer 503 once this tunnel is degraded
+    #[serde(default)]
+    pub locked: bool, // Refuse restart/pause/resume through the control API
+    #[serde(default)]
+    pub dscp: Option<u8>, // DSCP code point (0-63) ssh marks the tunnel's packets with
+    #[serde(default)]
+    pub env_file: Option<PathBuf>, // Dotenv-style variables for this tunnel's placeholders and ssh process only
+    #[serde(skip)]
+    pub env: HashMap<String, String>, // Variables read from env_file
+    #[serde(default)]
+    pub log_tag: Option<String>, // Short tag shown in log lines instead of the name
+    #[serde(default)]
+    pub verify: Option<VerifyConfig>, // Receive tunnels: check the forwarded service once after connecting
+    #[serde(default)]
+    pub gate: Option<String>, // Always connect through the gate with this name instead of the pool
+    #[serde(default)]
+    pub key_path: Option<PathBuf>, // Authenticate this tunnel with its own key instead of the gate's
+}
+
+impl TunnelConfig {
+    /// Name used for this tunnel in log lines: the log tag, or the tunnel name
+    pub fn log_name(&self) -> &str {
+        self.log_tag.as_deref().unwrap_or(&self.name)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,6 +195,145 @@ pub struct ConnectionLimits {
     pub max_attempts: u32,
     pub retry_window_secs: u64,
     pub max_backoff_secs: u64,
+    #[serde(default = "default_startup_grace_secs")]
+    pub startup_grace_secs: u64, // Report unconnected tunnels as starting, not inactive
+    #[serde(default)]
+    pub reconnect_on_network_change: bool, // Linux only: reconnect when the default route changes
+    #[serde(default)]
+    pub exit_on_auth_failure: bool, // Stop with exit code 3 instead of retrying rejected credentials
+    #[serde(default = "default_reconnect_stagger_ms")]
+    pub reconnect_stagger_ms: u64, // Window bulk reconnects are spread over
+    #[serde(default)]
+    pub restart_cooldown_secs: u64, // Minimum time between forced restarts of one tunnel
+    #[serde(default)]
+    pub max_tunnels: Option<usize>, // Enabled tunnels beyond this are not started
+}
+
+fn default_degraded_window_secs() -> u64 {
+    3600
+}
+
+fn default_startup_grace_secs() -> u64 {
+    120
+}
+
+fn default_reconnect_stagger_ms() -> u64 {
+    1000
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditConfig {
+    pub path: PathBuf, // JSON Lines file, appended to
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsdConfig {
+    pub addr: String, // host:port of the StatsD agent
+    #[serde(default = "default_statsd_prefix")]
+    pub prefix: String,
+    #[serde(default = "default_statsd_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default)]
+    pub dogstatsd: bool, // Tag metrics with the tunnel instead of putting it in the metric name
+}
+
+fn default_statsd_prefix() -> String {
+    "mtunnel".to_string()
+}
+
+fn default_statsd_interval_secs() -> u64 {
+    10
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricsTlsConfig {
+    pub cert_path: PathBuf, // PEM certificate chain
+    pub key_path: PathBuf,  // PEM private key
+}
+
+impl MetricsTlsConfig {
+    /// Catch unreadable or obviously wrong files at startup rather than when
+    /// the server first binds
+    fn validate(&self) -> Result<()> {
+        if !cfg!(feature = "tls") {
+            return Err(anyhow!(
+                "metrics_tls needs m-tunnel built with the tls feature"
+            ));
+        }
+
+        for (path, marker) in [
+            (&self.cert_path, "BEGIN CERTIFICATE"),
+            (&self.key_path, "PRIVATE KEY"),
+        ] {
+            let pem = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read metrics_tls file {}", path.display()))?;
+            if !pem.contains(marker) {
+                return Err(anyhow!(
+                    "metrics_tls file {} has no PEM {} block",
+                    path.display(),
+                    marker
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check run against a receive tunnel's local end once it connects. Exactly
+/// one of `tcp_banner_regex` and `http_path` is set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VerifyConfig {
+    #[serde(default)]
+    pub tcp_banner_regex: Option<String>, // The first line the service sends must match
+    #[serde(default)]
+    pub http_path: Option<String>, // GET this path and compare the response status
+    #[serde(default = "default_expected_status")]
+    pub expected_status: u16,
+    #[serde(default = "default_verify_timeout_secs")]
+    pub timeout_secs: u64, // Time allowed for the forward to come up and answer
+}
+
+fn default_expected_status() -> u16 {
+    200
+}
+
+fn default_verify_timeout_secs() -> u64 {
+    10
+}
+
+/// Weights of `mtunnel_health_score`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HealthConfig {
+    pub critical_tunnels: Vec<String>, // Tunnels whose uptime counts; all enabled tunnels when empty
+    pub window_secs: u64,              // Period recent reconnects and errors are counted over
+    pub up_weight: f64,
+    pub reconnect_weight: f64,
+    pub error_weight: f64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            critical_tunnels: Vec::new(),
+            window_secs: 300,
+            up_weight: 0.6,
+            reconnect_weight: 0.2,
+            error_weight: 0.2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeartbeatConfig {
+    #[serde(default = "default_heartbeat_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default)]
+    pub webhook_url: Option<String>, // Also POST each heartbeat here as JSON
+}
+
+fn default_heartbeat_interval_secs() -> u64 {
+    3600
 }
 
 impl Default for ConnectionLimits {
@@ -44,22 +342,193 @@ impl Default for ConnectionLimits {
             max_attempts: 5,
             retry_window_secs: 300,
             max_backoff_secs: 60,
+            startup_grace_secs: default_startup_grace_secs(),
+            reconnect_on_network_change: false,
+            exit_on_auth_failure: false,
+            reconnect_stagger_ms: default_reconnect_stagger_ms(),
+            restart_cooldown_secs: 0,
+            max_tunnels: None,
+        }
+    }
+}
+
+/// Legacy tunnel list read when the configuration file has no tunnels
+const LEGACY_TUNNELS_FILE: &str = "tunnels.conf";
+
+/// Files searched for a configuration, first match wins
+const CONFIG_PATHS: &[&str] = &[
+    "/etc/m-tunnel/config.toml",
+    "/etc/m-tunnel/config.json",
+    "/etc/m-tunnel/config.yaml",
+    "/etc/m-tunnel/config.yml",
+    "./config.toml",
+    "./config.json",
+    "./config.yaml",
+    "./config.yml",
+];
+
+/// Syntax of a configuration file, picked by its extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Format for a path, TOML unless the extension says otherwise
+    pub fn of(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => ConfigFormat::Json,
+            Some(ext) if ext.eq_ignore_ascii_case("yaml") || ext.eq_ignore_ascii_case("yml") => {
+                ConfigFormat::Yaml
+            }
+            _ => ConfigFormat::Toml,
+        }
+    }
+
+    fn parse(self, content: &str) -> Result<Config> {
+        let config = match self {
+            ConfigFormat::Toml => toml::from_str(content).map_err(anyhow::Error::from),
+            ConfigFormat::Json => serde_json::from_str(content).map_err(anyhow::Error::from),
+            ConfigFormat::Yaml => serde_yaml::from_str(content).map_err(anyhow::Error::from),
+        };
+        config.with_context(|| format!("Failed to parse {} configuration", self))
+    }
+}
+
+impl std::fmt::Display for ConfigFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigFormat::Toml => write!(f, "TOML"),
+            ConfigFormat::Json => write!(f, "JSON"),
+            ConfigFormat::Yaml => write!(f, "YAML"),
         }
     }
 }
 
 impl Config {
     pub fn load() -> Result<Self> {
-        // Load from TOML configuration
-        Self::load_toml()
+        Self::load_default_paths()
+    }
+
+    /// Load a configuration file given explicitly. Unlike `load`, a missing
+    /// file is an error rather than a reason to write a sample. Both block
+    /// while `config_url` is fetched, so async callers should run them with
+    /// `tokio::task::spawn_blocking`.
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        Self::from_content(path, &content)
+    }
+
+    /// Read tunnels in the legacy one-per-line format:
+    ///
+    /// ```text
+    /// send -- 127.0.0.1:22 to 192.168.1.1:2222
+    /// receive -- 0.0.0.0:8080 from 10.0.0.1:80
+    /// ```
+    ///
+    /// The local end comes first. Tunnels are enabled and named after their
+    /// direction and ports, e.g. `send-22-2222`.
+    pub fn parse_legacy_tunnels(path: &Path) -> Result<Vec<TunnelConfig>> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        let mut tunnels = Vec::new();
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let malformed =
+                |reason: String| anyhow!("{}:{}: {}", path.display(), index + 1, reason);
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let (direction, local, remote) = match words.as_slice() {
+                ["send", "--", local, "to", remote] => ("send", local, remote),
+                ["receive", "--", local, "from", remote] => ("receive", local, remote),
+                _ => {
+                    return Err(malformed(
+                        "expected \"send -- host:port to host:port\" or \"receive -- host:port from host:port\"".to_string(),
+                    ))
+                }
+            };
+            let (local_host, local_port) =
+                Self::parse_host_port(local).map_err(|e| malformed(format!("{:#}", e)))?;
+            let (remote_host, remote_port) =
+                Self::parse_host_port(remote).map_err(|e| malformed(format!("{:#}", e)))?;
+
+            // Through serde so every other field takes its usual default
+            let tunnel = serde_json::from_value(serde_json::json!({
+                "name": format!("{}-{}-{}", direction, local_port, remote_port),
+                "direction": direction,
+                "local_host": local_host,
+                "local_port": local_port,
+                "remote_host": remote_host,
+                "remote_port": remote_port,
+                "enabled": true,
+            }))?;
+            tunnels.push(tunnel);
+        }
+        Ok(tunnels)
+    }
+
+    /// Split `host:port`, or `[v6addr]:port`, rejecting ports outside 1-65535
+    pub fn parse_host_port(value: &str) -> Result<(String, u16)> {
+        let (host, port) = value
+            .rsplit_once(':')
+            .ok_or_else(|| anyhow!("{:?} is not host:port", value))?;
+        let host = host
+            .strip_prefix('[')
+            .and_then(|h| h.strip_suffix(']'))
+            .unwrap_or(host);
+        if host.is_empty() {
+            return Err(anyhow!("{:?} has no host", value));
+        }
+        let port = port
+            .parse::<u32>()
+            .map_err(|_| anyhow!("{:?} has an invalid port", value))?;
+        match u16::try_from(port) {
+            Ok(port) if port > 0 => Ok((host.to_string(), port)),
+            _ => Err(anyhow!(
+                "port {} in {:?} is out of range 1-65535",
+                port,
+                value
+            )),
+        }
+    }
+
+    pub fn enabled_tunnel_names(&self) -> Vec<String> {
+        self.tunnels
+            .iter()
+            .filter(|t| t.enabled)
+            .map(|t| t.name.clone())
+            .collect()
     }
 
-    fn load_toml() -> Result<Self> {
-        let config_paths = ["/etc/m-tunnel/config.toml", "./config.toml"];
+    /// Disable enabled tunnels beyond `limits.max_tunnels`, keeping them in
+    /// file order. Returns the names of the tunnels that were dropped.
+    pub fn apply_max_tunnels(&mut self) -> Vec<String> {
+        let Some(max) = self.limits.max_tunnels else {
+            return Vec::new();
+        };
 
+        self.tunnels
+            .iter_mut()
+            .filter(|t| t.enabled)
+            .skip(max)
+            .map(|t| {
+                t.enabled = false;
+                t.name.clone()
+            })
+            .collect()
+    }
+
+    fn load_default_paths() -> Result<Self> {
         // Check if any config file exists
         let mut config_exists = false;
-        for path in &config_paths {
+        for path in CONFIG_PATHS {
             if PathBuf::from(path).exists() {
                 config_exists = true;
                 break;
@@ -89,7 +558,7 @@ max_backoff_secs = 60
 # Example: Forward local port 8080 to remote port 80
 [[tunnels]]
 name = "web-tunnel"
-direction = "send"  # "send" for local→remote, "receive" for remote→local
+direction = "send"  # "send" for local→remote, "receive" for remote→local, "dynamic" for a SOCKS proxy
 local_host = "127.0.0.1"
 local_port = 8080
 remote_host = "127.0.0.1"
@@ -114,12 +583,605 @@ enabled = false  # Set to true when configured
         }
 
         // Try loading existing config
-        for path in &config_paths {
+        for path in CONFIG_PATHS {
             if let Ok(content) = fs::read_to_string(path) {
-                return toml::from_str(&content).context("Failed to parse TOML configuration");
+                return Self::from_content(Path::new(path), &content);
+            }
+        }
+
+        Err(anyhow!("No config file found"))
+    }
+
+    /// Parse a configuration file's content in the format its extension
+    /// names, then finish and validate it
+    fn from_content(path: &Path, content: &str) -> Result<Self> {
+        let invalid = || format!("Invalid config file {}", path.display());
+        let expanded = expand_env_vars(content);
+        let mut config = match ConfigFormat::of(path).parse(&expanded) {
+            Ok(config) => config,
+            Err(e) => {
+                // Placeholders left for env_file are only valid inside strings
+                let deferred: Vec<String> = deferred_pattern()
+                    .captures_iter(&expanded)
+                    .map(|caps| format!("${{{}}}", &caps[1]))
+                    .collect();
+                if deferred.is_empty() {
+                    return Err(e).with_context(invalid);
+                }
+                return Err(e)
+                    .context(format!(
+                        "unset variables without a default must be quoted to be filled from an env_file: {}",
+                        deferred.join(", ")
+                    ))
+                    .with_context(invalid);
             }
+        };
+        config.load_legacy_tunnels(path)?;
+        config.merge_remote_tunnels()?;
+        config.load_env_files()?;
+        config.expand_tunnel_env()?;
+        if let Some(name) = config.deferred_variable() {
+            return Err(unset_variable(content, &name)).with_context(invalid);
         }
+        config.validate()?;
+        Ok(config)
+    }
 
-        Err(anyhow!("No TOML config found"))
+    /// Fall back to a legacy `tunnels.conf` beside the configuration file when
+    /// the configuration itself defines no tunnels
+    fn load_legacy_tunnels(&mut self, config_path: &Path) -> Result<()> {
+        if !self.tunnels.is_empty() {
+            return Ok(());
+        }
+        let legacy = config_path.with_file_name(LEGACY_TUNNELS_FILE);
+        if legacy.is_file() {
+            self.tunnels = Self::parse_legacy_tunnels(&legacy)?;
+            log::info!(
+                "Loaded {} tunnels from legacy {}",
+                self.tunnels.len(),
+                legacy.display()
+            );
+        }
+        Ok(())
     }
+
+    /// Merge the tunnels served at `config_url` over the local ones, replacing
+    /// local tunnels of the same name. The response is cached so a failed fetch
+    /// falls back to the last good list instead of starting without tunnels.
+    fn merge_remote_tunnels(&mut self) -> Result<()> {
+        let Some(url) = self.config_url.clone() else {
+            return Ok(());
+        };
+
+        let remote = match fetch_remote_tunnels(&url) {
+            Ok(body) => {
+                if let Err(e) = fs::write(&self.config_url_cache, &body) {
+                    log::warn!(
+                        "Failed to cache tunnels from {} at {}: {}",
+                        url,
+                        self.config_url_cache.display(),
+                        e
+                    );
+                }
+                body
+            }
+            Err(e) => {
+                log::warn!("Failed to fetch tunnels from {}: {:#}", url, e);
+                match fs::read_to_string(&self.config_url_cache) {
+                    Ok(body) => {
+                        log::warn!(
+                            "Using cached tunnels from {}",
+                            self.config_url_cache.display()
+                        );
+                        body
+                    }
+                    Err(_) => {
+                        log::warn!("No cached tunnels available, using local tunnels only");
+                        return Ok(());
+                    }
+                }
+            }
+        };
+
+        let remote: RemoteTunnels =
+            serde_json::from_str(&remote).context("Failed to parse tunnels from config_url")?;
+        let count = remote.tunnels.len();
+        for tunnel in remote.tunnels {
+            self.tunnels.retain(|t| t.name != tunnel.name);
+            self.tunnels.push(tunnel);
+        }
+        log::info!("Merged {} tunnels from {}", count, url);
+
+        Ok(())
+    }
+
+    /// Read each tunnel's `env_file` into `env`. The variables stay with the
+    /// tunnel and are never set in the process environment.
+    fn load_env_files(&mut self) -> Result<()> {
+        for tunnel in &mut self.tunnels {
+            if let Some(path) = &tunnel.env_file {
+                // Reported as an unset variable once loading finishes
+                if deferred_variable(&path.to_string_lossy()).is_some() {
+                    continue;
+                }
+                tunnel.env = parse_env_file(path)
+                    .with_context(|| format!("Tunnel '{}' has an invalid env_file", tunnel.name))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fill the placeholders the process environment left unset from each
+    /// tunnel's own `env_file` variables
+    fn expand_tunnel_env(&mut self) -> Result<()> {
+        for tunnel in &mut self.tunnels {
+            if tunnel.env.is_empty() {
+                continue;
+            }
+            let mut fields = serde_json::to_value(&*tunnel)?;
+            fill_deferred(&mut fields, &tunnel.env);
+            let env = std::mem::take(&mut tunnel.env);
+            *tunnel = serde_json::from_value(fields)
+                .with_context(|| format!("Tunnel '{}'", tunnel.name))?;
+            tunnel.env = env;
+        }
+        Ok(())
+    }
+
+    /// First placeholder that neither the environment nor an env_file filled
+    fn deferred_variable(&self) -> Option<String> {
+        let passphrases = self.gates().iter().filter_map(|g| g.key_passphrase.clone());
+        serde_json::to_string(self)
+            .ok()
+            .into_iter()
+            .chain(passphrases)
+            .find_map(|text| deferred_variable(&text))
+    }
+
+    /// Gates tunnels may connect through: `[[gates]]` when present, else `[gate]`
+    pub fn gates(&self) -> &[SshConfig] {
+        if self.gates.is_empty() {
+            self.gate.as_slice()
+        } else {
+            &self.gates
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.gates().is_empty() {
+            return Err(anyhow!(
+                "No gate configured, add a [gate] section or [[gates]] entries"
+            ));
+        }
+
+        if let Some(tls) = &self.metrics_tls {
+            tls.validate()?;
+        }
+
+        let weights = [
+            self.health.up_weight,
+            self.health.reconnect_weight,
+            self.health.error_weight,
+        ];
+        if weights.iter().any(|w| *w < 0.0 || !w.is_finite()) || weights.iter().sum::<f64>() <= 0.0
+        {
+            return Err(anyhow!(
+                "[health] weights must be non-negative and not all zero"
+            ));
+        }
+
+        if self.gate.is_some() && !self.gates.is_empty() {
+            return Err(anyhow!("Configure either [gate] or [[gates]], not both"));
+        }
+
+        if let Some(gate) = self.gates().iter().find(|g| g.weight == 0) {
+            return Err(anyhow!("Gate '{}' has weight 0", gate.label()));
+        }
+
+        let mut gate_names = std::collections::HashSet::new();
+        for name in self.gates().iter().filter_map(|g| g.name.as_deref()) {
+            if !gate_names.insert(name) {
+                return Err(anyhow!("Gate name '{}' is used more than once", name));
+            }
+        }
+
+        // Metrics, controls and reload all key tunnels by name
+        let mut tunnel_names = std::collections::HashSet::new();
+        for tunnel in &self.tunnels {
+            if !tunnel_names.insert(tunnel.name.as_str()) {
+                return Err(anyhow!(
+                    "Tunnel name '{}' is used more than once",
+                    tunnel.name
+                ));
+            }
+            let direction = tunnel
+                .direction
+                .parse::<crate::tunnel_cli::TunnelDirection>()
+                .with_context(|| format!("Tunnel '{}'", tunnel.name))?;
+
+            if direction == crate::tunnel_cli::TunnelDirection::Dynamic {
+                if tunnel.local_host.starts_with("unix:") {
+                    return Err(anyhow!(
+                        "Tunnel '{}' is dynamic; ssh cannot serve a SOCKS proxy on a Unix socket",
+                        tunnel.name
+                    ));
+                }
+            } else if tunnel.remote_host.trim().is_empty() || tunnel.remote_port == 0 {
+                return Err(anyhow!(
+                    "Tunnel '{}' needs remote_host and remote_port for direction \"{}\"",
+                    tunnel.name,
+                    tunnel.direction
+                ));
+            }
+
+            if tunnel
+                .key_path
+                .as_ref()
+                .is_some_and(|p| p.as_os_str().is_empty())
+            {
+                return Err(anyhow!("Tunnel '{}' has an empty key_path", tunnel.name));
+            }
+
+            if let Some(gate) = tunnel.gate.as_deref() {
+                if !gate_names.contains(gate) {
+                    return Err(anyhow!(
+                        "Tunnel '{}' refers to gate '{}', which is not configured",
+                        tunnel.name,
+                        gate
+                    ));
+                }
+            }
+
+            if let Some(dscp) = tunnel.dscp.filter(|d| *d > 63) {
+                return Err(anyhow!(
+                    "Tunnel '{}' has dscp {}, expected 0 to 63",
+                    tunnel.name,
+                    dscp
+                ));
+            }
+
+            if let Some(verify) = &tunnel.verify {
+                if tunnel.direction != "receive" {
+                    return Err(anyhow!(
+                        "Tunnel '{}' has a verify block, which only receive tunnels support",
+                        tunnel.name
+                    ));
+                }
+                match (&verify.tcp_banner_regex, &verify.http_path) {
+                    (Some(pattern), None) => {
+                        regex::Regex::new(pattern).with_context(|| {
+                            format!("Tunnel '{}' has an invalid tcp_banner_regex", tunnel.name)
+                        })?;
+                    }
+                    (None, Some(path)) if path.starts_with('/') => {}
+                    (None, Some(_)) => {
+                        return Err(anyhow!(
+                            "Tunnel '{}' has a verify http_path that does not start with '/'",
+                            tunnel.name
+                        ));
+                    }
+                    _ => {
+                        return Err(anyhow!(
+                            "Tunnel '{}' verify needs exactly one of tcp_banner_regex and http_path",
+                            tunnel.name
+                        ));
+                    }
+                }
+            }
+        }
+
+        for gate in self.gates() {
+            if let Some(pattern) = &gate.banner_pattern {
+                regex::Regex::new(pattern).with_context(|| {
+                    format!("Gate '{}' has an invalid banner_pattern", gate.label())
+                })?;
+            }
+        }
+
+        for gate in self.gates() {
+            if gate.jump_host.is_some() && gate.pin_gate_ip {
+                return Err(anyhow!(
+                    "Gate '{}' sets both jump_host and pin_gate_ip; the gate is resolved by the jump host, not locally",
+                    gate.label()
+                ));
+            }
+            if gate
+                .jump_host
+                .as_deref()
+                .is_some_and(|j| j.trim().is_empty())
+            {
+                return Err(anyhow!("Gate '{}' has an empty jump_host", gate.label()));
+            }
+            if let Some(fingerprint) = &gate.host_key_fingerprint {
+                if !fingerprint.starts_with("SHA256:") {
+                    return Err(anyhow!(
+                        "Gate '{}' has host_key_fingerprint {:?}, expected the SHA256:... form ssh-keygen -l prints",
+                        gate.label(),
+                        fingerprint
+                    ));
+                }
+                if gate.known_hosts_path.is_some()
+                    || gate
+                        .host_key_policy
+                        .is_some_and(|p| p != HostKeyPolicy::Strict)
+                {
+                    return Err(anyhow!(
+                        "Gate '{}' pins host_key_fingerprint, which replaces known_hosts_path and host_key_policy",
+                        gate.label()
+                    ));
+                }
+                if gate.jump_host.is_some() || gate.use_ssh_config {
+                    return Err(anyhow!(
+                        "Gate '{}' pins host_key_fingerprint, which needs the gate reachable directly, without jump_host or use_ssh_config",
+                        gate.label()
+                    ));
+                }
+            }
+            if gate.key_passphrase.is_some() && gate.key_passphrase_env.is_some() {
+                return Err(anyhow!(
+                    "Gate '{}' sets both key_passphrase and key_passphrase_env",
+                    gate.label()
+                ));
+            }
+            if let Some(var) = &gate.key_passphrase_env {
+                if std::env::var_os(var).is_none() {
+                    return Err(anyhow!(
+                        "Gate '{}' reads its key passphrase from {}, which is not set",
+                        gate.label(),
+                        var
+                    ));
+                }
+            }
+            if gate.host_key_policy == Some(HostKeyPolicy::Off) && gate.known_hosts_path.is_some() {
+                return Err(anyhow!(
+                    "Gate '{}' sets known_hosts_path but host_key_policy is off",
+                    gate.label()
+                ));
+            }
+        }
+
+        for gate in self.gates() {
+            if gate.auth_method != AuthMethod::Agent {
+                continue;
+            }
+            if !gate.key_path.as_os_str().is_empty() {
+                return Err(anyhow!(
+                    "Gate '{}' uses auth_method agent, so its key_path would be ignored; remove it",
+                    gate.label()
+                ));
+            }
+            if gate.key_passphrase.is_some() || gate.key_passphrase_env.is_some() {
+                return Err(anyhow!(
+                    "Gate '{}' uses auth_method agent, which has no key file to unlock with a passphrase",
+                    gate.label()
+                ));
+            }
+        }
+
+        for gate in self.gates().iter().filter(|g| !g.use_ssh_config) {
+            if gate.user.is_empty() {
+                return Err(anyhow!(
+                    "Gate '{}' needs user unless use_ssh_config is set",
+                    gate.label()
+                ));
+            }
+            if gate.auth_method == AuthMethod::KeyFile && gate.key_path.as_os_str().is_empty() {
+                return Err(anyhow!(
+                    "Gate '{}' needs key_path unless auth_method is agent or use_ssh_config is set",
+                    gate.label()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Names of the top-level fields that differ between two versions of a
+/// configuration section, for reporting what a reload changed
+pub fn changed_fields<T: Serialize>(old: &T, new: &T) -> Vec<String> {
+    let (Ok(serde_json::Value::Object(old)), Ok(serde_json::Value::Object(new))) =
+        (serde_json::to_value(old), serde_json::to_value(new))
+    else {
+        return Vec::new();
+    };
+
+    let mut changed: Vec<String> = old
+        .iter()
+        .filter(|(field, value)| new.get(*field) != Some(*value))
+        .map(|(field, _)| field.clone())
+        .collect();
+    changed.extend(new.keys().filter(|f| !old.contains_key(*f)).cloned());
+    changed
+}
+
+/// Marks around the name of a placeholder left for the tunnels' env_file
+/// variables. Private-use characters, so they never occur in a real config.
+const DEFERRED_START: char = '\u{E000}';
+const DEFERRED_END: char = '\u{E001}';
+
+fn deferred_pattern() -> regex::Regex {
+    regex::Regex::new(&format!(
+        "{}([A-Za-z_][A-Za-z0-9_]*){}",
+        DEFERRED_START, DEFERRED_END
+    ))
+    .expect("valid pattern")
+}
+
+/// Name of the first placeholder in `text` still waiting for a value
+fn deferred_variable(text: &str) -> Option<String> {
+    deferred_pattern()
+        .captures(text)
+        .map(|caps| caps[1].to_string())
+}
+
+/// Replace deferred placeholders in every string of `value` that `vars` defines
+fn fill_deferred(value: &mut serde_json::Value, vars: &HashMap<String, String>) {
+    match value {
+        serde_json::Value::String(text) => {
+            let filled = deferred_pattern().replace_all(text, |caps: &regex::Captures| {
+                vars.get(&caps[1])
+                    .cloned()
+                    .unwrap_or_else(|| caps[0].to_string())
+            });
+            *text = filled.into_owned();
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(|v| fill_deferred(v, vars)),
+        serde_json::Value::Object(fields) => {
+            fields.values_mut().for_each(|v| fill_deferred(v, vars))
+        }
+        _ => {}
+    }
+}
+
+/// Error for a variable without a default that nothing set, at the line of
+/// its first placeholder
+fn unset_variable(content: &str, name: &str) -> anyhow::Error {
+    let placeholder = format!("${{{}}}", name);
+    let line = content
+        .find(&placeholder)
+        .map_or(1, |at| content[..at].matches('\n').count() + 1);
+    anyhow!(
+        "line {}: environment variable {} is not set and has no default",
+        line,
+        name
+    )
+}
+
+/// Replace `${VAR}` with the variable's value and `${VAR:-default}` with the
+/// value or, when it is unset or empty, the default. `$${` stands for a
+/// literal `${`. An unset variable without a default is left as a deferred
+/// placeholder for the tunnel's env_file to fill.
+fn expand_env_vars(content: &str) -> String {
+    let pattern = regex::Regex::new(r"\$\$\{|\$\{([A-Za-z_][A-Za-z0-9_]*)(:-([^}]*))?\}")
+        .expect("valid pattern");
+
+    let mut expanded = String::with_capacity(content.len());
+    let mut last = 0;
+    for caps in pattern.captures_iter(content) {
+        let token = caps.get(0).expect("whole match");
+        expanded.push_str(&content[last..token.start()]);
+        last = token.end();
+
+        let Some(name) = caps.get(1) else {
+            expanded.push_str("${");
+            continue;
+        };
+        match (std::env::var(name.as_str()).ok(), caps.get(3)) {
+            (Some(value), Some(default)) if value.is_empty() => expanded.push_str(default.as_str()),
+            (Some(value), _) => expanded.push_str(&value),
+            (None, Some(default)) => expanded.push_str(default.as_str()),
+            (None, None) => {
+                expanded.push(DEFERRED_START);
+                expanded.push_str(name.as_str());
+                expanded.push(DEFERRED_END);
+            }
+        }
+    }
+    expanded.push_str(&content[last..]);
+
+    expanded
+}
+
+/// Parse a dotenv-style file: `KEY=value` lines with an optional `export `
+/// prefix, `#` comments, and single-quoted (literal) or double-quoted
+/// (`\n`, `\"`, `\\` escapes) values
+fn parse_env_file(path: &Path) -> Result<HashMap<String, String>> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+    let mut vars = HashMap::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let malformed = |reason: &str| anyhow!("{}:{}: {}", path.display(), index + 1, reason);
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, raw) = line
+            .split_once('=')
+            .ok_or_else(|| malformed("expected KEY=value"))?;
+        let key = key.trim();
+        let valid_key = key
+            .chars()
+            .enumerate()
+            .all(|(i, c)| c == '_' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit()));
+        if key.is_empty() || !valid_key {
+            return Err(malformed(&format!("invalid variable name {:?}", key)));
+        }
+
+        let raw = raw.trim();
+        let value = if let Some(rest) = raw.strip_prefix('"') {
+            let mut value = String::new();
+            let mut chars = rest.chars();
+            let mut closed = false;
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => {
+                        closed = true;
+                        break;
+                    }
+                    '\\' => match chars.next() {
+                        Some('n') => value.push('\n'),
+                        Some(c) => value.push(c),
+                        None => break,
+                    },
+                    c => value.push(c),
+                }
+            }
+            let trailing = chars.as_str().trim();
+            if !closed || !(trailing.is_empty() || trailing.starts_with('#')) {
+                return Err(malformed("unterminated or malformed double-quoted value"));
+            }
+            value
+        } else if let Some(rest) = raw.strip_prefix('\'') {
+            let (value, trailing) = rest
+                .split_once('\'')
+                .ok_or_else(|| malformed("unterminated single-quoted value"))?;
+            let trailing = trailing.trim();
+            if !(trailing.is_empty() || trailing.starts_with('#')) {
+                return Err(malformed("unexpected text after single-quoted value"));
+            }
+            value.to_string()
+        } else {
+            // Unquoted values end at an inline " #" comment
+            raw.split(" #")
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_string()
+        };
+
+        vars.insert(key.to_string(), value);
+    }
+
+    Ok(vars)
+}
+
+/// Fetch the raw `config_url` body. `M_TUNNEL_CONFIG_AUTH` is sent as the
+/// Authorization header when set.
+#[cfg(feature = "remote-config")]
+fn fetch_remote_tunnels(url: &str) -> Result<String> {
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(std::time::Duration::from_secs(10)))
+        .build()
+        .into();
+
+    let mut request = agent.get(url);
+    if let Ok(auth) = std::env::var("M_TUNNEL_CONFIG_AUTH") {
+        request = request.header("Authorization", &auth);
+    }
+
+    let body = request.call()?.body_mut().read_to_string()?;
+    // Only cache what parses
+    serde_json::from_str::<RemoteTunnels>(&body).context("Invalid tunnel list")?;
+    Ok(body)
+}
+
+#[cfg(not(feature = "remote-config"))]
+fn fetch_remote_tunnels(_url: &str) -> Result<String> {
+    Err(anyhow!(
+        "config_url needs m-tunnel built with the remote-config feature"
+    ))
 }
@@ -0,0 +1,193 @@
+use anyhow::Result;
+use log::info;
+#[cfg(unix)]
+use std::sync::{Arc, RwLock};
+use std::{net::IpAddr, path::PathBuf};
+use tokio::signal;
+
+#[cfg(unix)]
+use crate::config::changed_fields;
+use crate::config::Config;
+#[cfg(unix)]
+use crate::metrics::MetricsCollector;
+
+/// Load the given configuration file, or search the default locations. Runs
+/// on the blocking pool, as fetching `config_url` can take seconds.
+pub async fn load_config(path: Option<PathBuf>) -> Result<Config> {
+    tokio::task::spawn_blocking(move || match path {
+        Some(path) => Config::load_from(&path),
+        None => Config::load(),
+    })
+    .await?
+}
+
+/// Set up logging for the daemon: `RUST_LOG` filtering, info by default,
+/// each line tagged "M-Tunnel" instead of the module path
+pub fn init_logging() {
+    if std::env::var("RUST_LOG").is_err() {
+        std::env::set_var("RUST_LOG", "info");
+    }
+
+    env_logger::Builder::from_default_env()
+        .format(|buf, record| {
+            use std::io::Write;
+
+            // Color codes for different log levels (only for the level word)
+            let colored_level = match record.level() {
+                log::Level::Error => format!("\x1b[91m{}\x1b[0m", record.level()), // Bright red
+                log::Level::Warn => format!("\x1b[93m{}\x1b[0m", record.level()),  // Bright yellow
+                log::Level::Info => format!("\x1b[92m{}\x1b[0m", record.level()),  // Bright green
+                log::Level::Debug => format!("\x1b[94m{}\x1b[0m", record.level()), // Bright blue
+                log::Level::Trace => format!("\x1b[90m{}\x1b[0m", record.level()), // Dark gray
+            };
+
+            writeln!(
+                buf,
+                "[{} {} M-Tunnel] {}",
+                chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+                colored_level,
+                record.args()
+            )
+        })
+        .init();
+}
+
+/// Log the gates tunnels will connect through
+pub fn log_gates(config: &Config) {
+    for gate in config.gates() {
+        if gate.use_ssh_config {
+            info!("M-Tunnel-Gate : {} (from ssh config)", gate.label());
+        } else if crate::tunnel_cli::is_server_internal_ip(&gate.host) {
+            let default_name = "server_internal".to_string();
+            let server_display = gate.server_name.as_ref().unwrap_or(&default_name);
+            info!("M-Tunnel-Gate : {}@{}", gate.user, server_display);
+        } else {
+            info!("M-Tunnel-Gate : {}@{}", gate.user, gate.host);
+        }
+    }
+}
+
+/// Address the metrics port listens on: `METRICS_BIND`, or all IPv4 interfaces
+pub fn metrics_bind() -> Result<IpAddr> {
+    match std::env::var("METRICS_BIND") {
+        Ok(bind) => bind.trim().parse().map_err(|_| {
+            anyhow::anyhow!(
+                "METRICS_BIND {:?} is not an IP address, expected e.g. 127.0.0.1 or ::1",
+                bind
+            )
+        }),
+        Err(_) => Ok(IpAddr::from([0, 0, 0, 0])),
+    }
+}
+
+/// Log the tunnels `Config::apply_max_tunnels` left out
+pub fn warn_dropped(config: &Config, dropped: &[String]) {
+    if !dropped.is_empty() {
+        log::warn!(
+            "max_tunnels is {}, not starting {} tunnels: {}",
+            config.limits.max_tunnels.unwrap_or_default(),
+            dropped.len(),
+            dropped.join(", ")
+        );
+    }
+}
+
+/// Top-level sections a reload puts into effect; `limits` is compared field
+/// by field
+#[cfg(unix)]
+const RELOADED_SECTIONS: &[&str] = &["tunnels", "gate", "gates", "limits"];
+
+/// `[limits]` settings a reload puts into effect; `max_tunnels` is applied
+/// to the reloaded tunnel list before it is sent
+#[cfg(unix)]
+const RELOADED_LIMITS: &[&str] = &["max_attempts", "retry_window_secs", "max_tunnels"];
+
+/// Reload the configuration on SIGHUP. Added, changed, removed and disabled
+/// tunnels, gate changes and the connection limiter settings are applied to
+/// the running manager; unchanged tunnels keep their connection. Other
+/// settings only take effect after a restart.
+#[cfg(unix)]
+pub async fn reload_on_hangup(
+    config_path: Option<PathBuf>,
+    config: Arc<RwLock<Config>>,
+    metrics: Arc<MetricsCollector>,
+    reloader: tokio::sync::mpsc::UnboundedSender<Config>,
+) {
+    use signal::unix::SignalKind;
+
+    let mut hangup = match signal::unix::signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            log::warn!("Failed to install SIGHUP handler: {}", e);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        info!("Received SIGHUP, reloading configuration");
+        let mut reloaded = match load_config(config_path.clone()).await {
+            Ok(config) => config,
+            Err(e) => {
+                log::error!("Reload failed, keeping the running configuration: {:#}", e);
+                continue;
+            }
+        };
+        let dropped = reloaded.apply_max_tunnels();
+        warn_dropped(&reloaded, &dropped);
+        metrics.record_tunnels_dropped(dropped.len());
+
+        let pending = {
+            let mut current = config.write().unwrap();
+            let mut pending: Vec<String> = changed_fields(&*current, &reloaded)
+                .into_iter()
+                .filter(|field| !RELOADED_SECTIONS.contains(&field.as_str()))
+                .collect();
+            pending.extend(
+                changed_fields(&current.limits, &reloaded.limits)
+                    .into_iter()
+                    .filter(|field| !RELOADED_LIMITS.contains(&field.as_str()))
+                    .map(|field| format!("limits.{}", field)),
+            );
+
+            current.tunnels = reloaded.tunnels.clone();
+            current.gate = reloaded.gate.clone();
+            current.gates = reloaded.gates.clone();
+            current.limits.max_attempts = reloaded.limits.max_attempts;
+            current.limits.retry_window_secs = reloaded.limits.retry_window_secs;
+            current.limits.max_tunnels = reloaded.limits.max_tunnels;
+            metrics.configure_health(current.health.clone(), current.enabled_tunnel_names());
+            pending
+        };
+        if !pending.is_empty() {
+            log::warn!(
+                "Restart to apply the changes to {}; only tunnels, gates and connection limits are reloaded",
+                pending.join(", ")
+            );
+        }
+
+        if reloader.send(reloaded).is_err() {
+            break;
+        }
+    }
+}
+
+/// Wait for Ctrl-C, or on Unix also SIGTERM, the stop signal of systemd and docker
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use signal::unix::SignalKind;
+
+        match signal::unix::signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = signal::ctrl_c() => info!("Received SIGINT"),
+                    _ = terminate.recv() => info!("Received SIGTERM"),
+                }
+                return;
+            }
+            Err(e) => log::warn!("Failed to install SIGTERM handler: {}", e),
+        }
+    }
+
+    signal::ctrl_c().await.unwrap();
+}
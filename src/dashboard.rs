@@ -0,0 +1,106 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::metrics::{MetricsCollector, TunnelStatus};
+
+/// Seconds between automatic page reloads
+const REFRESH_SECS: u32 = 10;
+
+/// Render the self-contained status page served at `GET /`
+pub fn render(config: &Config, metrics: &MetricsCollector) -> String {
+    let stats = metrics.get_summary();
+    let mut rows = String::new();
+
+    for tunnel in &config.tunnels {
+        let stat = stats.get(&tunnel.name);
+        let status = stat.map_or(&TunnelStatus::Disconnected, |s| &s.status);
+        let (label, color) = match status {
+            _ if !tunnel.enabled => ("Disabled", "#888"),
+            TunnelStatus::Connected => (status.label(), "#2e7d32"),
+            TunnelStatus::Connecting | TunnelStatus::WaitingLocal => (status.label(), "#f9a825"),
+            TunnelStatus::Paused => (status.label(), "#888"),
+            TunnelStatus::ForwardClosed | TunnelStatus::Error | TunnelStatus::Disconnected => {
+                (status.label(), "#c62828")
+            }
+        };
+
+        let _ = write!(
+            rows,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td style=\"color:{}\">&#9679; {}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape_html(&tunnel.name),
+            escape_html(&tunnel.direction),
+            escape_html(stat.and_then(|s| s.gate.as_deref()).unwrap_or("-")),
+            color,
+            label,
+            stat.map_or_else(|| "-".to_string(), |s| format_uptime(s.uptime)),
+            stat.map_or_else(
+                || "-".to_string(),
+                |s| format!("{} / {}", format_bytes(s.bytes_sent), format_bytes(s.bytes_received))
+            ),
+            stat.map(|s| s.reconnect_count).unwrap_or(0),
+            escape_html(stat.and_then(|s| s.last_error.as_deref()).unwrap_or("")),
+        );
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta http-equiv="refresh" content="{refresh}">
+<title>M-Tunnel</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; }}
+th, td {{ padding: 0.4em 1em; border-bottom: 1px solid #ddd; text-align: left; }}
+</style>
+</head>
+<body>
+<h1>M-Tunnel v{version}</h1>
+<table>
+<tr><th>Tunnel</th><th>Direction</th><th>Gate</th><th>Status</th><th>Uptime</th><th>Sent / received</th><th>Reconnects</th><th>Last error</th></tr>
+{rows}
+</table>
+</body>
+</html>
+"#,
+        refresh = REFRESH_SECS,
+        version = env!("CARGO_PKG_VERSION"),
+        rows = rows,
+    )
+}
+
+/// Connected time as e.g. `3d 4h`, `2h 5m` or `42s`, `-` when not connected
+fn format_uptime(uptime: Duration) -> String {
+    let secs = uptime.as_secs();
+    match secs {
+        0 => "-".to_string(),
+        1..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {}s", secs / 60, secs % 60),
+        3600..=86399 => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d {}h", secs / 86400, secs % 86400 / 3600),
+    }
+}
+
+/// Byte count with a binary unit, e.g. `512 B` or `1.5 MiB`
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
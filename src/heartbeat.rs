@@ -0,0 +1,76 @@
+use log::info;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time;
+
+use crate::config::HeartbeatConfig;
+use crate::metrics::{MetricsCollector, TunnelStatus};
+
+/// Report aggregate health every `interval_secs`, whether or not anything
+/// changed, so an unattended device proves it is alive. The report is always
+/// logged and also posted to `webhook_url` when one is set.
+pub fn spawn(config: HeartbeatConfig, metrics: Arc<MetricsCollector>) {
+    #[cfg(not(feature = "webhook"))]
+    if config.webhook_url.is_some() {
+        log::warn!("[heartbeat] webhook_url needs the webhook feature, logging heartbeats only");
+    }
+
+    tokio::spawn(async move {
+        let mut interval = time::interval(Duration::from_secs(config.interval_secs.max(1)));
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+        // The first tick fires immediately; the first report is due after one interval
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+
+            let stats = metrics.get_summary();
+            let report = serde_json::json!({
+                "event": "heartbeat",
+                "timestamp": chrono::Utc::now().to_rfc3339(),
+                "version": env!("CARGO_PKG_VERSION"),
+                "uptime_secs": metrics.uptime().as_secs(),
+                "tunnels_up": stats
+                    .values()
+                    .filter(|s| s.status == TunnelStatus::Connected)
+                    .count(),
+                // Running tunnels, so reloads that add or remove some count
+                "tunnels_total": stats.len(),
+                "bytes_sent": stats.values().map(|s| s.bytes_sent).sum::<u64>(),
+                "bytes_received": stats.values().map(|s| s.bytes_received).sum::<u64>(),
+            });
+
+            info!(
+                "Heartbeat: {}/{} tunnels up, {} bytes sent, {} bytes received, uptime {}s",
+                report["tunnels_up"],
+                report["tunnels_total"],
+                report["bytes_sent"],
+                report["bytes_received"],
+                report["uptime_secs"]
+            );
+
+            #[cfg(feature = "webhook")]
+            if let Some(url) = config.webhook_url.clone() {
+                let body = report.to_string();
+                let result = tokio::task::spawn_blocking(move || post(&url, &body)).await;
+                if let Ok(Err(e)) = result {
+                    log::warn!("Failed to send heartbeat: {:#}", e);
+                }
+            }
+        }
+    });
+}
+
+#[cfg(feature = "webhook")]
+fn post(url: &str, body: &str) -> anyhow::Result<()> {
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(Duration::from_secs(10)))
+        .build()
+        .into();
+
+    agent
+        .post(url)
+        .header("Content-Type", "application/json")
+        .send(body)?;
+    Ok(())
+}
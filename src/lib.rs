@@ -0,0 +1,56 @@
+//! M-Tunnel as a library, for services that run their tunnels in-process
+//! instead of starting the `m-tunnel` binary.
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//!
+//! use m_tunnel::{Config, MetricsCollector, TunnelManager};
+//!
+//! #[tokio::main]
+//! async fn main() -> anyhow::Result<()> {
+//!     // Only needed when a gate sets key_passphrase
+//!     if let Some(answered) = m_tunnel::answer_askpass() {
+//!         std::process::exit(if answered { 0 } else { 1 });
+//!     }
+//!
+//!     let config = Config::load_from("tunnels.toml".as_ref())?;
+//!     let metrics = Arc::new(MetricsCollector::new());
+//!     let manager = TunnelManager::new(config, Arc::clone(&metrics)).await?;
+//!
+//!     tokio::select! {
+//!         result = manager.start() => result?,
+//!         _ = tokio::signal::ctrl_c() => {}
+//!     }
+//!     manager.shutdown().await
+//! }
+//! ```
+//!
+//! The `m-tunnel` binary is built from the same pieces: `server` serves the
+//! metrics and control API (metrics feature), [`daemon`] loads and reloads
+//! the configuration and waits for the shutdown signals, and [`client`]
+//! queries a running instance over its control socket.
+
+pub mod client;
+pub mod config;
+pub mod daemon;
+pub mod metrics;
+#[cfg(feature = "metrics")]
+pub mod server;
+pub mod tunnel_cli;
+
+mod audit;
+#[cfg(feature = "dashboard")]
+mod dashboard;
+mod netwatch;
+
+// Used by the m-tunnel binary, not part of the library API
+#[doc(hidden)]
+pub mod heartbeat;
+#[doc(hidden)]
+pub mod otel;
+#[doc(hidden)]
+pub mod statsd;
+
+pub use config::{Config, SshConfig, TunnelConfig};
+pub use metrics::MetricsCollector;
+pub use tunnel_cli::{answer_askpass, TunnelManager};
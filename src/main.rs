@@ -1,41 +1,75 @@
-mod config;
-mod metrics;
-mod tunnel_cli;
+#[cfg(unix)]
+use m_tunnel::daemon::reload_on_hangup;
+use m_tunnel::daemon::{self, load_config, metrics_bind, shutdown_signal, warn_dropped};
+#[cfg(feature = "metrics")]
+use m_tunnel::server::start_metrics_server;
+use m_tunnel::{client, heartbeat, metrics, otel, statsd, tunnel_cli};
 
 use anyhow::Result;
-use config::Config;
 use log::info;
-use metrics::MetricsCollector;
-use std::{net::IpAddr, sync::Arc};
-use tokio::signal;
-
-/// Check if IP is a server internal network (hide completely)
-fn is_server_internal_ip(ip_or_host: &str) -> bool {
-    if let Ok(ip) = ip_or_host.parse::<IpAddr>() {
-        match ip {
-            IpAddr::V4(ipv4) => {
-                let octets = ipv4.octets();
-                // Server internal networks: 192.168.x.x, 10.x.x.x, 172.16-31.x.x
-                (octets[0] == 192 && octets[1] == 168)
-                    || (octets[0] == 10)
-                    || (octets[0] == 172 && octets[1] >= 16 && octets[1] <= 31)
-            }
-            IpAddr::V6(_) => false, // Assume IPv6 is not server internal for now
-        }
-    } else {
-        false
+use metrics::{MetricsCollector, TunnelStatus};
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+use tunnel_cli::TunnelFailure;
+
+/// Process exit codes, so wrapper scripts and systemd can tell why m-tunnel stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExitCode {
+    Clean = 0,
+    Failure = 1,
+    ConfigError = 2,
+    AuthFailure = 3,
+}
+
+impl From<ExitCode> for std::process::ExitCode {
+    fn from(code: ExitCode) -> Self {
+        std::process::ExitCode::from(code as u8)
     }
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> std::process::ExitCode {
+    match run().await {
+        Ok(code) => code.into(),
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::Failure.into()
+        }
+    }
+}
+
+async fn run() -> Result<ExitCode> {
     // Check for help before doing anything else
     let args: Vec<String> = std::env::args().collect();
+    if let Some(answered) = tunnel_cli::answer_askpass() {
+        return Ok(if answered {
+            ExitCode::Clean
+        } else {
+            ExitCode::Failure
+        });
+    }
     if args.contains(&"--help".to_string()) || args.contains(&"-h".to_string()) {
         print_help();
-        return Ok(());
+        return Ok(ExitCode::Clean);
+    }
+
+    match args.get(1).map(String::as_str) {
+        Some("status") => return run_status(&args[2..]).await,
+        Some("events") => return run_events(&args[2..]).await,
+        _ => {}
     }
 
+    let config_path = match config_arg(&args) {
+        Ok(path) => path,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            return Ok(ExitCode::ConfigError);
+        }
+    };
+
     // Check for dry run early to avoid config loading
     let dry_run = args.contains(&"--dry-run".to_string());
     if dry_run {
@@ -43,84 +77,116 @@ async fn main() -> Result<()> {
         println!("Command line arguments parsed successfully");
         println!("CLI implementation available");
         println!("Dry run completed - would proceed with tunnel creation");
-        return Ok(());
+        return Ok(ExitCode::Clean);
     }
 
-    // Initialize logger with info as default level
-    if std::env::var("RUST_LOG").is_err() {
-        std::env::set_var("RUST_LOG", "info");
-    }
-
-    // Custom logger format to show "M-Tunnel" instead of module path
-    env_logger::Builder::from_default_env()
-        .format(|buf, record| {
-            use std::io::Write;
-
-            // Color codes for different log levels (only for the level word)
-            let colored_level = match record.level() {
-                log::Level::Error => format!("\x1b[91m{}\x1b[0m", record.level()), // Bright red
-                log::Level::Warn => format!("\x1b[93m{}\x1b[0m", record.level()),  // Bright yellow
-                log::Level::Info => format!("\x1b[92m{}\x1b[0m", record.level()),  // Bright green
-                log::Level::Debug => format!("\x1b[94m{}\x1b[0m", record.level()), // Bright blue
-                log::Level::Trace => format!("\x1b[90m{}\x1b[0m", record.level()), // Dark gray
-            };
-
-            writeln!(
-                buf,
-                "[{} {} M-Tunnel] {}",
-                chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
-                colored_level,
-                record.args()
-            )
-        })
-        .init();
-
+    daemon::init_logging();
     info!("Starting M-Tunnel v{}", env!("CARGO_PKG_VERSION"));
 
+    let otel_guard = otel::init()?;
+
     // Load configuration (supports both legacy and new TOML formats)
-    let config = Config::load()?;
+    let mut config = match load_config(config_path.clone()).await {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("Configuration error: {:#}", e);
+            return Ok(ExitCode::ConfigError);
+        }
+    };
+
+    let metrics_bind = match metrics_bind() {
+        Ok(addr) => addr,
+        Err(e) => {
+            log::error!("Configuration error: {:#}", e);
+            return Ok(ExitCode::ConfigError);
+        }
+    };
 
     info!("Loaded configuration with {} tunnels", config.tunnels.len());
-    if is_server_internal_ip(&config.gate.host) {
-        let default_name = "server_internal".to_string();
-        let server_display = config.gate.server_name.as_ref().unwrap_or(&default_name);
-        info!("M-Tunnel-Gate : {}@{}", config.gate.user, server_display);
-    } else {
-        info!("M-Tunnel-Gate : {}@{}", config.gate.user, config.gate.host);
-    }
+    let dropped = config.apply_max_tunnels();
+    warn_dropped(&config, &dropped);
+    daemon::log_gates(&config);
 
     // Initialize metrics collector
     let metrics = Arc::new(MetricsCollector::new());
+    metrics.record_tunnels_dropped(dropped.len());
+    metrics.configure_health(config.health.clone(), config.enabled_tunnel_names());
 
-    // Start metrics server if enabled
-    if let Ok(metrics_port_str) = std::env::var("METRICS_PORT") {
-        if let Ok(metrics_port) = metrics_port_str.parse::<u16>() {
-            let metrics_clone = Arc::clone(&metrics);
-            tokio::spawn(async move {
-                if let Err(e) = start_metrics_server(metrics_clone, metrics_port).await {
-                    log::warn!("Metrics server failed: {}", e);
-                }
-            });
-            info!("Metrics server enabled on port {}", metrics_port);
-        }
+    if let Some(statsd_config) = &config.statsd {
+        statsd::spawn(statsd_config.clone(), Arc::clone(&metrics));
     }
 
+    if let Some(heartbeat_config) = &config.heartbeat {
+        heartbeat::spawn(heartbeat_config.clone(), Arc::clone(&metrics));
+    }
+
+    // Effective configuration, served on the metrics server for debugging
+    // and updated when tunnels are reloaded
+    let effective_config = Arc::new(RwLock::new(config.clone()));
+
     // Create tunnel manager - use CLI implementation for optimal performance
-    let tunnel_manager = tunnel_cli::TunnelManager::new(config, metrics).await?;
+    let tunnel_manager = match tunnel_cli::TunnelManager::new(config, Arc::clone(&metrics)).await {
+        Ok(manager) => manager,
+        Err(e) => {
+            log::error!("Configuration error: {:#}", e);
+            return Ok(ExitCode::ConfigError);
+        }
+    };
+
+    #[cfg(unix)]
+    tokio::spawn(reload_on_hangup(
+        config_path,
+        Arc::clone(&effective_config),
+        Arc::clone(&metrics),
+        tunnel_manager.reloader(),
+    ));
+
+    // Start metrics server if enabled, on TCP and/or the control socket
+    let metrics_addr = std::env::var("METRICS_PORT")
+        .ok()
+        .and_then(|port| port.parse::<u16>().ok())
+        .map(|port| SocketAddr::new(metrics_bind, port));
+    let control_socket = effective_config.read().unwrap().control_socket.clone();
+    #[cfg(feature = "metrics")]
+    if metrics_addr.is_some() || control_socket.is_some() {
+        let metrics_clone = Arc::clone(&metrics);
+        let controls = tunnel_manager.controls();
+        tokio::spawn(async move {
+            if let Err(e) =
+                start_metrics_server(metrics_clone, effective_config, controls, metrics_addr).await
+            {
+                log::warn!("Metrics server failed: {}", e);
+            }
+        });
+        if let Some(addr) = metrics_addr {
+            info!("Metrics server enabled on {}", addr);
+        }
+    }
+    #[cfg(not(feature = "metrics"))]
+    if metrics_addr.is_some() || control_socket.is_some() {
+        log::error!(
+            "METRICS_PORT and control_socket need the metrics feature, which this build does not include; rebuild with --features metrics"
+        );
+    }
 
     // Set up graceful shutdown
     let shutdown_handle = {
         tokio::spawn(async move {
-            signal::ctrl_c().await.unwrap();
+            shutdown_signal().await;
             info!("Shutdown signal received");
         })
     };
 
     // Start tunnel management
+    let mut exit_code = ExitCode::Clean;
     tokio::select! {
         result = tunnel_manager.start() => {
             if let Err(e) = result {
                 log::error!("Tunnel manager failed: {}", e);
+                exit_code = match e.downcast_ref::<TunnelFailure>() {
+                    Some(TunnelFailure::AuthFailure) => ExitCode::AuthFailure,
+                    _ => ExitCode::Failure,
+                };
             }
         }
         _ = shutdown_handle => {
@@ -131,37 +197,57 @@ async fn main() -> Result<()> {
     // Clean shutdown
     tunnel_manager.shutdown().await?;
     info!("Clean shutdown completed");
+    otel::shutdown(otel_guard);
 
-    Ok(())
+    Ok(exit_code)
 }
 
-#[cfg(feature = "metrics")]
-async fn start_metrics_server(metrics: Arc<MetricsCollector>, port: u16) -> Result<()> {
-    use warp::Filter;
-
-    let metrics_route = warp::path("metrics").map(move || {
-        warp::reply::with_header(
-            metrics.export_prometheus(),
-            "content-type",
-            "text/plain; version=0.0.4",
-        )
-    });
+/// The file named by `--config` or `M_TUNNEL_CONFIG`, if any
+fn config_arg(args: &[String]) -> Result<Option<PathBuf>> {
+    match args.iter().position(|a| a == "--config") {
+        Some(i) => args
+            .get(i + 1)
+            .map(|path| Some(PathBuf::from(path)))
+            .ok_or_else(|| anyhow::anyhow!("--config requires a path")),
+        None => Ok(std::env::var_os("M_TUNNEL_CONFIG").map(PathBuf::from)),
+    }
+}
 
-    let health_route =
-        warp::path("health").map(|| warp::reply::with_status("OK", warp::http::StatusCode::OK));
+/// `m-tunnel status [--socket <path>]`: print each tunnel's state as reported
+/// by a running instance over its control socket
+async fn run_status(args: &[String]) -> Result<ExitCode> {
+    let tunnels = client::tunnel_statuses(socket_arg(args)?).await?;
 
-    let routes = metrics_route.or(health_route);
+    if tunnels.is_empty() {
+        println!("No tunnels reported yet");
+    }
+    for (name, status) in tunnels {
+        println!(
+            "{:<32} {}",
+            name,
+            status.as_ref().map_or("Unknown", TunnelStatus::label)
+        );
+    }
 
-    info!("Starting metrics server on 0.0.0.0:{}", port);
-    warp::serve(routes).run(([0, 0, 0, 0], port)).await;
+    Ok(ExitCode::Clean)
+}
 
-    Ok(())
+/// `m-tunnel events [--socket <path>]`: print the running instance's live
+/// event feed, one JSON object per line, until it exits or we are interrupted
+async fn run_events(args: &[String]) -> Result<ExitCode> {
+    client::follow_events(socket_arg(args)?, |event| println!("{}", event)).await?;
+    Ok(ExitCode::Clean)
 }
 
-#[cfg(not(feature = "metrics"))]
-async fn start_metrics_server(_metrics: Arc<MetricsCollector>, _port: u16) -> Result<()> {
-    log::warn!("Metrics feature not enabled, skipping metrics server");
-    Ok(())
+/// Control socket given with `--socket`, or the default location
+fn socket_arg(args: &[String]) -> Result<&str> {
+    match args.iter().position(|a| a == "--socket") {
+        Some(i) => args
+            .get(i + 1)
+            .map(String::as_str)
+            .ok_or_else(|| anyhow::anyhow!("--socket requires a path")),
+        None => Ok(client::DEFAULT_CONTROL_SOCKET),
+    }
 }
 
 fn print_help() {
@@ -170,6 +256,8 @@ fn print_help() {
     println!();
     println!("USAGE:");
     println!("    m-tunnel [OPTIONS]");
+    println!("    m-tunnel status [--socket <path>]");
+    println!("    m-tunnel events [--socket <path>]");
     println!();
     println!("OPTIONS:");
     println!("    --dry-run           Validate configuration without creating tunnels");
@@ -179,9 +267,25 @@ fn print_help() {
     println!("ENVIRONMENT VARIABLES:");
     println!("    M_TUNNEL_CONFIG=<path>  Configuration file path");
     println!("    METRICS_PORT=<port>     Enable metrics server on specified port");
+    println!("    METRICS_BIND=<address>  Metrics server listen address (default 0.0.0.0)");
+    println!("    METRICS_TOKEN=<token>   Require this bearer token on the metrics port");
+    println!("    OTEL_EXPORTER_OTLP_ENDPOINT=<url>  Export connection traces (otel feature)");
+    println!();
+    println!("TUNNEL DIRECTIONS:");
+    println!("    send        Publish local_host:local_port on the gate as remote_port (ssh -R)");
+    println!("    receive     Bring remote_host:remote_port to local_host:local_port (ssh -L)");
+    println!("    dynamic     SOCKS proxy on local_host:local_port through the gate (ssh -D)");
+    println!();
+    println!("EXIT CODES:");
+    println!("    0    Clean shutdown");
+    println!("    1    Runtime failure");
+    println!("    2    Configuration error");
+    println!("    3    Authentication rejected (with limits.exit_on_auth_failure)");
     println!();
     println!("EXAMPLES:");
     println!("    m-tunnel --dry-run");
+    println!("    m-tunnel status --socket /run/m-tunnel.sock");
+    println!("    m-tunnel events | jq .");
     println!("    m-tunnel --config /etc/m-tunnel/custom.toml");
     println!();
     println!("PERFORMANCE:");
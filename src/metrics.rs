@@ -1,20 +1,53 @@
-use serde::Serialize;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    sync::{Arc, RwLock},
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
     time::{Duration, Instant},
 };
+use tokio::sync::broadcast;
+
+use crate::config::HealthConfig;
+
+/// Number of distinct errors kept per tunnel
+const ERROR_HISTORY_LEN: usize = 10;
+
+/// Events buffered per live feed subscriber before a slow one starts missing them
+const EVENT_BUFFER: usize = 256;
+
+/// A distinct error message and how often it has occurred
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorRecord {
+    pub last_seen: DateTime<Utc>,
+    pub message: String,
+    pub count: u64,
+}
 
 #[derive(Debug, Clone, Serialize)]
 pub struct TunnelStats {
     pub tunnel_id: String,
     pub status: TunnelStatus,
-    pub uptime: Duration,
+    pub uptime: Duration, // Time connected since the last (re)connect
+    #[serde(skip)]
+    connected_since: Option<Instant>,
+    pub ready: bool, // Forward confirmed working since the last (re)connect
     pub reconnect_count: u64,
+    pub forward_closed_count: u64,
     pub bytes_sent: u64,
     pub bytes_received: u64,
     pub last_error: Option<String>,
+    pub error_history: VecDeque<ErrorRecord>, // Oldest first, deduplicated by message
+    pub gate: Option<String>,
+    pub gate_selections: HashMap<String, u64>,
     pub connection_latency: Option<Duration>,
+    pub degraded: bool, // Reconnected too often within its window; never cleared
+    pub dscp: Option<u8>,
+    pub verified: Option<bool>, // Result of the last post-connect verification
+    pub verify_failures: u64,
 }
 
 impl Default for TunnelStats {
@@ -23,27 +56,150 @@ impl Default for TunnelStats {
             tunnel_id: String::new(),
             status: TunnelStatus::Disconnected,
             uptime: Duration::from_secs(0),
+            connected_since: None,
+            ready: false,
             reconnect_count: 0,
+            forward_closed_count: 0,
             bytes_sent: 0,
             bytes_received: 0,
             last_error: None,
+            error_history: VecDeque::new(),
+            gate: None,
+            gate_selections: HashMap::new(),
             connection_latency: None,
+            degraded: false,
+            dscp: None,
+            verified: None,
+            verify_failures: 0,
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, PartialEq)]
-#[allow(dead_code)]
+/// A tunnel's contribution to readiness, as listed by `/healthz/ready`
+#[derive(Debug, Clone, Serialize)]
+pub struct TunnelReadiness {
+    pub name: String,
+    pub status: TunnelStatus,
+    pub critical: bool, // Only critical tunnels hold readiness down
+    pub ready: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum TunnelStatus {
     Connected,
     Connecting,
     Disconnected,
     Error,
+    ForwardClosed,
+    WaitingLocal,
+    Paused,
+}
+
+impl TunnelStatus {
+    /// Numeric value exported as `mtunnel_status`
+    pub fn code(&self) -> u8 {
+        match self {
+            TunnelStatus::Connected => 1,
+            TunnelStatus::Connecting => 2,
+            TunnelStatus::Disconnected => 3,
+            TunnelStatus::Error => 4,
+            TunnelStatus::ForwardClosed => 5,
+            TunnelStatus::WaitingLocal => 6,
+            TunnelStatus::Paused => 7,
+        }
+    }
+
+    /// Human-readable name, as shown by the dashboard and `m-tunnel status`
+    pub fn label(&self) -> &'static str {
+        match self {
+            TunnelStatus::Connected => "Connected",
+            TunnelStatus::Connecting => "Connecting",
+            TunnelStatus::Disconnected => "Disconnected",
+            TunnelStatus::Error => "Error",
+            TunnelStatus::ForwardClosed => "Forward closed",
+            TunnelStatus::WaitingLocal => "Waiting for local service",
+            TunnelStatus::Paused => "Paused",
+        }
+    }
+}
+
+/// Live feed entry for a tunnel changing status
+#[derive(Serialize)]
+struct StatusEvent<'a> {
+    timestamp: String,
+    event: &'static str,
+    tunnel: &'a str,
+    from: &'a TunnelStatus,
+    to: &'a TunnelStatus,
+}
+
+/// Counts one live task or process for as long as it is held
+pub struct TaskGuard(Arc<AtomicUsize>);
+
+impl TaskGuard {
+    fn new(counter: &Arc<AtomicUsize>) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(Arc::clone(counter))
+    }
+}
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 pub struct MetricsCollector {
     stats: Arc<RwLock<HashMap<String, TunnelStats>>>,
     start_time: Instant,
+    active_tasks: Arc<AtomicUsize>,
+    ssh_processes: Arc<AtomicUsize>,
+    gate_versions: RwLock<HashMap<String, String>>,
+    gate_pinned_ips: RwLock<HashMap<String, IpAddr>>,
+    health_failing: AtomicBool,
+    tunnels_dropped: AtomicUsize,
+    metrics_server_restarts: AtomicUsize,
+    events: broadcast::Sender<String>,
+    health: RwLock<HealthScoring>,
+}
+
+/// Inputs to `mtunnel_health_score`
+#[derive(Default)]
+struct HealthScoring {
+    config: HealthConfig,
+    critical: Vec<String>,
+    reconnects: VecDeque<Instant>,
+    errors: VecDeque<Instant>,
+}
+
+impl HealthScoring {
+    fn prune(&mut self) {
+        let window = Duration::from_secs(self.config.window_secs);
+        for times in [&mut self.reconnects, &mut self.errors] {
+            while times.front().is_some_and(|t| t.elapsed() > window) {
+                times.pop_front();
+            }
+        }
+    }
+}
+
+/// A tunnel's stats, created with its id filled in on first use
+fn tunnel_entry<'a>(
+    stats: &'a mut HashMap<String, TunnelStats>,
+    tunnel_id: &str,
+) -> &'a mut TunnelStats {
+    stats
+        .entry(tunnel_id.to_string())
+        .or_insert_with(|| TunnelStats {
+            tunnel_id: tunnel_id.to_string(),
+            ..TunnelStats::default()
+        })
+}
+
+impl Default for MetricsCollector {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MetricsCollector {
@@ -51,33 +207,373 @@ impl MetricsCollector {
         Self {
             stats: Arc::new(RwLock::new(HashMap::new())),
             start_time: Instant::now(),
+            active_tasks: Arc::new(AtomicUsize::new(0)),
+            ssh_processes: Arc::new(AtomicUsize::new(0)),
+            gate_versions: RwLock::new(HashMap::new()),
+            gate_pinned_ips: RwLock::new(HashMap::new()),
+            health_failing: AtomicBool::new(false),
+            tunnels_dropped: AtomicUsize::new(0),
+            metrics_server_restarts: AtomicUsize::new(0),
+            events: broadcast::channel(EVENT_BUFFER).0,
+            health: RwLock::new(HealthScoring::default()),
         }
     }
 
+    pub fn uptime(&self) -> Duration {
+        self.start_time.elapsed()
+    }
+
+    /// Count a running tunnel task until the guard is dropped
+    pub fn track_task(&self) -> TaskGuard {
+        TaskGuard::new(&self.active_tasks)
+    }
+
+    /// Count a spawned ssh process until the guard is dropped
+    pub fn track_ssh_process(&self) -> TaskGuard {
+        TaskGuard::new(&self.ssh_processes)
+    }
+
+    /// Count tunnels refused because of `max_tunnels`
+    pub fn record_tunnels_dropped(&self, count: usize) {
+        self.tunnels_dropped.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn record_metrics_server_restart(&self) {
+        self.metrics_server_restarts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Sender for the live event feed; each event is one JSON object
+    pub fn events(&self) -> broadcast::Sender<String> {
+        self.events.clone()
+    }
+
+    pub fn subscribe_events(&self) -> broadcast::Receiver<String> {
+        self.events.subscribe()
+    }
+
     pub fn update_tunnel_status(&self, tunnel_id: &str, status: TunnelStatus) {
         let mut stats = self.stats.write().unwrap();
-        if let Some(tunnel_stats) = stats.get_mut(tunnel_id) {
-            tunnel_stats.status = status;
+        let stat = tunnel_entry(&mut stats, tunnel_id);
+
+        if stat.status != status {
+            let event = StatusEvent {
+                timestamp: Utc::now().to_rfc3339(),
+                event: "status",
+                tunnel: tunnel_id,
+                from: &stat.status,
+                to: &status,
+            };
+            if let Ok(event) = serde_json::to_string(&event) {
+                // Nobody listening is not an error
+                let _ = self.events.send(event);
+            }
+        }
+        if status == TunnelStatus::Connected {
+            stat.connected_since.get_or_insert_with(Instant::now);
+        } else {
+            stat.connected_since = None;
+            stat.ready = false;
+        }
+        stat.status = status;
+    }
+
+    /// Record that a connected tunnel's forward works, making it count as ready
+    /// until its next status change
+    pub fn mark_ready(&self, tunnel_id: &str) {
+        let mut stats = self.stats.write().unwrap();
+        tunnel_entry(&mut stats, tunnel_id).ready = true;
+    }
+
+    /// Whether m-tunnel is ready: healthy, and every critical tunnel's forward
+    /// confirmed working. Also returns each tunnel's part in that, critical
+    /// tunnels that have no stats included.
+    pub fn readiness(&self) -> (bool, Vec<TunnelReadiness>) {
+        let critical = self.health.read().unwrap().critical.clone();
+        let stats = self.stats.read().unwrap();
+
+        let mut tunnels: Vec<TunnelReadiness> = stats
+            .values()
+            .map(|s| TunnelReadiness {
+                name: s.tunnel_id.clone(),
+                status: s.status.clone(),
+                critical: critical.contains(&s.tunnel_id),
+                ready: s.ready,
+            })
+            .collect();
+        for name in critical.iter().filter(|n| !stats.contains_key(*n)) {
+            tunnels.push(TunnelReadiness {
+                name: name.clone(),
+                status: TunnelStatus::Disconnected,
+                critical: true,
+                ready: false,
+            });
+        }
+        tunnels.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let ready = self.is_healthy() && tunnels.iter().all(|t| t.ready || !t.critical);
+        (ready, tunnels)
+    }
+
+    /// Add a tunnel with default stats so it is reported before its first
+    /// status change
+    pub fn register_tunnel(&self, tunnel_id: &str) {
+        tunnel_entry(&mut self.stats.write().unwrap(), tunnel_id);
+    }
+
+    /// Forget a tunnel that is no longer configured
+    pub fn remove_tunnel(&self, tunnel_id: &str) {
+        self.stats.write().unwrap().remove(tunnel_id);
+    }
+
+    /// Remember the DSCP value configured for a tunnel
+    pub fn record_dscp(&self, tunnel_id: &str, dscp: u8) {
+        let mut stats = self.stats.write().unwrap();
+        tunnel_entry(&mut stats, tunnel_id).dscp = Some(dscp);
+    }
+
+    /// Record how long a tunnel took from starting ssh to a working forward
+    pub fn set_latency(&self, tunnel_id: &str, latency: Duration) {
+        let mut stats = self.stats.write().unwrap();
+        tunnel_entry(&mut stats, tunnel_id).connection_latency = Some(latency);
+    }
+
+    /// Record the outcome of a tunnel's post-connect verification
+    pub fn record_verification(&self, tunnel_id: &str, passed: bool) {
+        let mut stats = self.stats.write().unwrap();
+        let stat = tunnel_entry(&mut stats, tunnel_id);
+        stat.verified = Some(passed);
+        if !passed {
+            stat.verify_failures += 1;
+        }
+    }
+
+    /// Set the health score weights and the tunnels it treats as critical
+    pub fn configure_health(&self, config: HealthConfig, enabled_tunnels: Vec<String>) {
+        let mut health = self.health.write().unwrap();
+        health.critical = if config.critical_tunnels.is_empty() {
+            enabled_tunnels
+        } else {
+            config.critical_tunnels.clone()
+        };
+        health.config = config;
+    }
+
+    /// Composite health between 0 and 1: the weighted mean of the fraction
+    /// of critical tunnels connected, `1 / (1 + r)` for r reconnects per
+    /// critical tunnel within the window, and the same for errors. With the
+    /// default weights of 0.6, 0.2 and 0.2:
+    ///
+    /// ```
+    /// use m_tunnel::{config::HealthConfig, metrics::TunnelStatus, MetricsCollector};
+    ///
+    /// let metrics = MetricsCollector::new();
+    /// metrics.configure_health(HealthConfig::default(), vec!["a".into(), "b".into()]);
+    /// metrics.update_tunnel_status("a", TunnelStatus::Connected);
+    /// metrics.update_tunnel_status("b", TunnelStatus::Connected);
+    /// assert_eq!(metrics.health_score(), 1.0);
+    ///
+    /// // Half the critical tunnels down: 0.6 * 0.5 + 0.2 + 0.2
+    /// metrics.update_tunnel_status("b", TunnelStatus::Error);
+    /// assert!((metrics.health_score() - 0.7).abs() < 1e-9);
+    ///
+    /// // And two errors across the two tunnels: 0.6 * 0.5 + 0.2 + 0.2 / (1 + 1)
+    /// metrics.record_error("b", "Connection refused");
+    /// metrics.record_error("b", "Connection refused");
+    /// assert!((metrics.health_score() - 0.6).abs() < 1e-9);
+    ///
+    /// // And two reconnects: 0.6 * 0.5 + 0.2 / (1 + 1) + 0.2 / (1 + 1)
+    /// metrics.increment_reconnect("b");
+    /// metrics.increment_reconnect("b");
+    /// assert!((metrics.health_score() - 0.5).abs() < 1e-9);
+    /// ```
+    pub fn health_score(&self) -> f64 {
+        let mut health = self.health.write().unwrap();
+        health.prune();
+
+        let stats = self.stats.read().unwrap();
+        let critical = health.critical.len().max(1) as f64;
+        let up = health
+            .critical
+            .iter()
+            .filter(|id| {
+                stats
+                    .get(*id)
+                    .is_some_and(|s| s.status == TunnelStatus::Connected)
+            })
+            .count() as f64;
+
+        let up_score = if health.critical.is_empty() {
+            1.0
+        } else {
+            up / critical
+        };
+        let reconnect_score = 1.0 / (1.0 + health.reconnects.len() as f64 / critical);
+        let error_score = 1.0 / (1.0 + health.errors.len() as f64 / critical);
+
+        let config = &health.config;
+        let total = config.up_weight + config.reconnect_weight + config.error_weight;
+        if total <= 0.0 {
+            return up_score;
         }
+        (config.up_weight * up_score
+            + config.reconnect_weight * reconnect_score
+            + config.error_weight * error_score)
+            / total
     }
 
-    #[allow(dead_code)]
     pub fn increment_reconnect(&self, tunnel_id: &str) {
+        self.health
+            .write()
+            .unwrap()
+            .reconnects
+            .push_back(Instant::now());
         let mut stats = self.stats.write().unwrap();
-        stats
-            .entry(tunnel_id.to_string())
-            .or_default()
-            .reconnect_count += 1;
+        tunnel_entry(&mut stats, tunnel_id).reconnect_count += 1;
+    }
+
+    /// Flag a tunnel as degraded, optionally failing `/health` as well.
+    /// Returns true the first time the tunnel is flagged.
+    pub fn mark_degraded(&self, tunnel_id: &str, fails_health: bool) -> bool {
+        if fails_health {
+            self.health_failing.store(true, Ordering::Relaxed);
+        }
+        let mut stats = self.stats.write().unwrap();
+        let stat = tunnel_entry(&mut stats, tunnel_id);
+        !std::mem::replace(&mut stat.degraded, true)
+    }
+
+    /// False once a tunnel with `degraded_fails_health` has been flagged degraded
+    pub fn is_healthy(&self) -> bool {
+        !self.health_failing.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_forward_closed(&self, tunnel_id: &str) {
+        let mut stats = self.stats.write().unwrap();
+        tunnel_entry(&mut stats, tunnel_id).forward_closed_count += 1;
+    }
+
+    /// Record a connection error, folding repeats of a known message into its count
+    pub fn record_error(&self, tunnel_id: &str, message: &str) {
+        self.health
+            .write()
+            .unwrap()
+            .errors
+            .push_back(Instant::now());
+        let mut stats = self.stats.write().unwrap();
+        let tunnel_stats = tunnel_entry(&mut stats, tunnel_id);
+
+        let mut record = match tunnel_stats
+            .error_history
+            .iter()
+            .position(|r| r.message == message)
+        {
+            Some(index) => tunnel_stats.error_history.remove(index).unwrap(),
+            None => ErrorRecord {
+                last_seen: Utc::now(),
+                message: message.to_string(),
+                count: 0,
+            },
+        };
+        record.last_seen = Utc::now();
+        record.count += 1;
+
+        tunnel_stats.error_history.push_back(record);
+        if tunnel_stats.error_history.len() > ERROR_HISTORY_LEN {
+            tunnel_stats.error_history.pop_front();
+        }
+        tunnel_stats.last_error = Some(message.to_string());
+    }
+
+    /// Record the gate chosen for a tunnel's next connection attempt
+    pub fn record_gate_selection(&self, tunnel_id: &str, gate: &str) {
+        let mut stats = self.stats.write().unwrap();
+        let tunnel_stats = tunnel_entry(&mut stats, tunnel_id);
+        tunnel_stats.gate = Some(gate.to_string());
+        *tunnel_stats
+            .gate_selections
+            .entry(gate.to_string())
+            .or_default() += 1;
+    }
+
+    /// Remember the SSH server version a gate identified as. Returns true when
+    /// it is new or changed, so callers can log it once per gate.
+    pub fn record_gate_ssh_version(&self, gate: &str, version: &str) -> bool {
+        let mut versions = self.gate_versions.write().unwrap();
+        if versions.get(gate).map(String::as_str) == Some(version) {
+            return false;
+        }
+        versions.insert(gate.to_string(), version.to_string());
+        true
+    }
+
+    pub fn record_gate_pinned_ip(&self, gate: &str, ip: IpAddr) {
+        self.gate_pinned_ips
+            .write()
+            .unwrap()
+            .insert(gate.to_string(), ip);
     }
 
-    #[allow(dead_code)]
     pub fn get_summary(&self) -> HashMap<String, TunnelStats> {
+        let mut stats = self.stats.read().unwrap().clone();
+        for stat in stats.values_mut() {
+            stat.uptime = stat.connected_since.map_or(Duration::ZERO, |t| t.elapsed());
+        }
+        stats
+    }
+
+    /// Export per-tunnel metrics in InfluxDB line protocol, one `mtunnel`
+    /// point per tunnel. Commas, equals signs and spaces in tag values are
+    /// escaped:
+    ///
+    /// ```
+    /// let metrics = m_tunnel::MetricsCollector::new();
+    /// metrics.register_tunnel("db main,region=eu");
+    ///
+    /// let output = metrics.export_influx_line();
+    /// assert!(output.starts_with(r"mtunnel,tunnel=db\ main\,region\=eu,status=Disconnected reconnects=0i,"));
+    /// ```
+    pub fn export_influx_line(&self) -> String {
         let stats = self.stats.read().unwrap();
-        stats.clone()
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+
+        let mut output = String::new();
+        for (id, stat) in stats.iter() {
+            output.push_str(&format!(
+                "mtunnel,tunnel={},status={} reconnects={}i,forward_closed={}i,bytes_sent={}i,bytes_received={}i",
+                escape_influx_tag(id),
+                escape_influx_tag(&format!("{:?}", stat.status)),
+                stat.reconnect_count,
+                stat.forward_closed_count,
+                stat.bytes_sent,
+                stat.bytes_received
+            ));
+            if let Some(latency) = stat.connection_latency {
+                output.push_str(&format!(",latency_ms={}", latency.as_secs_f64() * 1000.0));
+            }
+            output.push_str(&format!(" {}\n", timestamp));
+        }
+
+        output
     }
 
-    /// Export metrics in Prometheus format
+    /// Export metrics in Prometheus format. Label values are escaped, so a
+    /// tunnel name cannot end its label early or add series of its own:
+    ///
+    /// ```
+    /// let metrics = m_tunnel::MetricsCollector::new();
+    /// metrics.register_tunnel("db\"} 1\nmtunnel_status{tunnel=\"fake");
+    ///
+    /// let output = metrics.export_prometheus();
+    /// assert!(output.contains(r#"mtunnel_status{tunnel="db\"} 1\nmtunnel_status{tunnel=\"fake"} 3"#));
+    /// assert!(!output.lines().any(|l| l.starts_with(r#"mtunnel_status{tunnel="fake"#)));
+    /// ```
     pub fn export_prometheus(&self) -> String {
+        // Takes the stats lock itself
+        let health_score = self.health_score();
+
         let stats = self.stats.read().unwrap();
         let mut output = String::new();
 
@@ -88,25 +584,252 @@ impl MetricsCollector {
             self.start_time.elapsed().as_secs()
         ));
 
-        for (id, stat) in stats.iter() {
-            output.push_str(&format!(
-                "mtunnel_reconnects_total{{tunnel=\"{}\"}} {}\n",
-                id, stat.reconnect_count
-            ));
+        output.push_str("# HELP mtunnel_degraded Whether any tunnel has been flagged degraded\n");
+        output.push_str("# TYPE mtunnel_degraded gauge\n");
+        output.push_str(&format!(
+            "mtunnel_degraded {}\n",
+            u8::from(stats.values().any(|s| s.degraded))
+        ));
 
-            let status_value = match stat.status {
-                TunnelStatus::Connected => 1,
-                TunnelStatus::Connecting => 2,
-                TunnelStatus::Disconnected => 3,
-                TunnelStatus::Error => 4,
-            };
+        output.push_str(
+            "# HELP mtunnel_health_score Composite health from 0 (down) to 1 (healthy)\n",
+        );
+        output.push_str("# TYPE mtunnel_health_score gauge\n");
+        output.push_str(&format!("mtunnel_health_score {:.3}\n", health_score));
 
-            output.push_str(&format!(
-                "mtunnel_status{{tunnel=\"{}\"}} {}\n",
-                id, status_value
-            ));
+        output.push_str("# HELP mtunnel_active_tasks Running tunnel tasks\n");
+        output.push_str("# TYPE mtunnel_active_tasks gauge\n");
+        output.push_str(&format!(
+            "mtunnel_active_tasks {}\n",
+            self.active_tasks.load(Ordering::Relaxed)
+        ));
+
+        output.push_str(
+            "# HELP mtunnel_tunnels_dropped_total Enabled tunnels not started because of max_tunnels\n",
+        );
+        output.push_str("# TYPE mtunnel_tunnels_dropped_total counter\n");
+        output.push_str(&format!(
+            "mtunnel_tunnels_dropped_total {}\n",
+            self.tunnels_dropped.load(Ordering::Relaxed)
+        ));
+
+        output.push_str(
+            "# HELP mtunnel_metrics_server_restarts_total Times the metrics listener was restarted\n",
+        );
+        output.push_str("# TYPE mtunnel_metrics_server_restarts_total counter\n");
+        output.push_str(&format!(
+            "mtunnel_metrics_server_restarts_total {}\n",
+            self.metrics_server_restarts.load(Ordering::Relaxed)
+        ));
+
+        output.push_str("# HELP mtunnel_ssh_processes Live ssh child processes\n");
+        output.push_str("# TYPE mtunnel_ssh_processes gauge\n");
+        output.push_str(&format!(
+            "mtunnel_ssh_processes {}\n",
+            self.ssh_processes.load(Ordering::Relaxed)
+        ));
+
+        if let Some(rss) = resident_memory_bytes() {
+            output.push_str("# HELP mtunnel_resident_memory_bytes Resident set size\n");
+            output.push_str("# TYPE mtunnel_resident_memory_bytes gauge\n");
+            output.push_str(&format!("mtunnel_resident_memory_bytes {}\n", rss));
         }
 
+        let versions = self.gate_versions.read().unwrap();
+        if !versions.is_empty() {
+            output.push_str(
+                "# HELP mtunnel_gate_ssh_version SSH server version reported by each gate\n",
+            );
+            output.push_str("# TYPE mtunnel_gate_ssh_version gauge\n");
+            for (gate, version) in versions.iter() {
+                output.push_str(&format!(
+                    "mtunnel_gate_ssh_version{{gate=\"{}\",version=\"{}\"}} 1\n",
+                    escape_label(gate),
+                    escape_label(version)
+                ));
+            }
+        }
+
+        let pinned = self.gate_pinned_ips.read().unwrap();
+        if !pinned.is_empty() {
+            output.push_str("# HELP mtunnel_gate_pinned_ip Address each pinned gate connects to\n");
+            output.push_str("# TYPE mtunnel_gate_pinned_ip gauge\n");
+            for (gate, ip) in pinned.iter() {
+                output.push_str(&format!(
+                    "mtunnel_gate_pinned_ip{{gate=\"{}\",ip=\"{}\"}} 1\n",
+                    escape_label(gate),
+                    ip
+                ));
+            }
+        }
+
+        // Each family's series follow its own HELP and TYPE lines, as the
+        // exposition format requires
+        let tunnels: Vec<(String, &TunnelStats)> = stats
+            .iter()
+            .map(|(id, stat)| (format!("tunnel=\"{}\"", escape_label(id)), stat))
+            .collect();
+
+        push_family(
+            &mut output,
+            "mtunnel_reconnects_total",
+            "counter",
+            "Reconnect attempts per tunnel",
+            tunnels
+                .iter()
+                .map(|(labels, s)| (labels.clone(), s.reconnect_count.to_string()))
+                .collect(),
+        );
+
+        push_family(
+            &mut output,
+            "mtunnel_forward_closed_total",
+            "counter",
+            "Times the gate closed a tunnel's forward while ssh stayed connected",
+            tunnels
+                .iter()
+                .map(|(labels, s)| (labels.clone(), s.forward_closed_count.to_string()))
+                .collect(),
+        );
+
+        push_family(
+            &mut output,
+            "mtunnel_tunnel_dscp",
+            "gauge",
+            "DSCP value configured for a tunnel's traffic",
+            tunnels
+                .iter()
+                .filter_map(|(labels, s)| s.dscp.map(|dscp| (labels.clone(), dscp.to_string())))
+                .collect(),
+        );
+
+        push_family(
+            &mut output,
+            "mtunnel_connection_latency_seconds",
+            "gauge",
+            "Time from starting ssh until the local forward accepted connections",
+            tunnels
+                .iter()
+                .filter_map(|(labels, s)| {
+                    s.connection_latency
+                        .map(|latency| (labels.clone(), format!("{:.3}", latency.as_secs_f64())))
+                })
+                .collect(),
+        );
+
+        push_family(
+            &mut output,
+            "mtunnel_tunnel_verified",
+            "gauge",
+            "Whether the last check of a receive tunnel's forwarded service passed",
+            tunnels
+                .iter()
+                .filter_map(|(labels, s)| {
+                    s.verified
+                        .map(|verified| (labels.clone(), u8::from(verified).to_string()))
+                })
+                .collect(),
+        );
+
+        push_family(
+            &mut output,
+            "mtunnel_verify_failures_total",
+            "counter",
+            "Failed checks of a receive tunnel's forwarded service",
+            tunnels
+                .iter()
+                .filter(|(_, s)| s.verified.is_some())
+                .map(|(labels, s)| (labels.clone(), s.verify_failures.to_string()))
+                .collect(),
+        );
+
+        push_family(
+            &mut output,
+            "mtunnel_gate_selected",
+            "counter",
+            "Connection attempts per tunnel and gate",
+            tunnels
+                .iter()
+                .flat_map(|(labels, s)| {
+                    s.gate_selections.iter().map(move |(gate, count)| {
+                        (
+                            format!("{},gate=\"{}\"", labels, escape_label(gate)),
+                            count.to_string(),
+                        )
+                    })
+                })
+                .collect(),
+        );
+
+        push_family(
+            &mut output,
+            "mtunnel_status",
+            "gauge",
+            "Tunnel status: 1 connected, 2 connecting, 3 disconnected, 4 error, 5 forward closed, 6 waiting for local service, 7 paused",
+            tunnels
+                .iter()
+                .map(|(labels, s)| (labels.clone(), s.status.code().to_string()))
+                .collect(),
+        );
+
         output
     }
 }
+
+/// Append a metric family: its HELP and TYPE lines, then one series per
+/// label set and value. Families without series are left out.
+fn push_family(
+    output: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    series: Vec<(String, String)>,
+) {
+    if series.is_empty() {
+        return;
+    }
+
+    output.push_str(&format!("# HELP {} {}\n", name, help));
+    output.push_str(&format!("# TYPE {} {}\n", name, kind));
+    for (labels, value) in series {
+        output.push_str(&format!("{}{{{}}} {}\n", name, labels, value));
+    }
+}
+
+/// Escape an InfluxDB tag key or value: commas, equals signs and spaces
+fn escape_influx_tag(value: &str) -> String {
+    value
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+/// Escape a Prometheus label value so it can't break out of its quotes
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Resident set size of this process. Read from the VmRSS line of
+/// `/proc/self/status`, which is already in kB, so the page size needed to
+/// interpret `/proc/self/statm` doesn't have to be looked up.
+#[cfg(target_os = "linux")]
+fn resident_memory_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kb = status
+        .lines()
+        .find_map(|l| l.strip_prefix("VmRSS:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn resident_memory_bytes() -> Option<u64> {
+    None
+}
@@ -0,0 +1,79 @@
+#[cfg(target_os = "linux")]
+use log::{debug, info};
+#[cfg(target_os = "linux")]
+use std::time::Duration;
+#[cfg(target_os = "linux")]
+use tokio::time;
+
+/// How often the routing table is sampled for default-route changes
+#[cfg(target_os = "linux")]
+const ROUTE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Watch the default routes and call `on_change` whenever they differ from
+/// the previous sample (wifi to ethernet, VPN up/down, new DHCP gateway).
+///
+/// Reads `/proc/net/route` and `/proc/net/ipv6_route` rather than holding a
+/// netlink socket, which keeps the watcher dependency free at the cost of up
+/// to one poll interval of latency.
+#[cfg(target_os = "linux")]
+pub async fn watch_default_route<F: Fn()>(on_change: F) {
+    let mut interval = time::interval(ROUTE_POLL_INTERVAL);
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+
+    let mut current = default_routes().await;
+    debug!("Watching default routes: {:?}", current);
+
+    loop {
+        interval.tick().await;
+
+        let routes = default_routes().await;
+        if routes != current {
+            info!(
+                "Default route changed ({:?} -> {:?}), reconnecting tunnels",
+                current, routes
+            );
+            current = routes;
+            on_change();
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn watch_default_route<F: Fn()>(_on_change: F) {
+    log::warn!("reconnect_on_network_change is only supported on Linux, ignoring");
+}
+
+/// Collect "interface gateway" pairs of the IPv4 and IPv6 default routes
+#[cfg(target_os = "linux")]
+async fn default_routes() -> Vec<String> {
+    let mut routes = Vec::new();
+
+    // Iface Destination Gateway ...
+    if let Ok(table) = tokio::fs::read_to_string("/proc/net/route").await {
+        for fields in table
+            .lines()
+            .skip(1)
+            .map(|l| l.split_whitespace().collect::<Vec<_>>())
+        {
+            if fields.len() > 2 && fields[1] == "00000000" {
+                routes.push(format!("{} {}", fields[0], fields[2]));
+            }
+        }
+    }
+
+    // Destination PrefixLen Source SourcePrefixLen NextHop Metric RefCnt Use Flags Iface
+    let any = "0".repeat(32);
+    if let Ok(table) = tokio::fs::read_to_string("/proc/net/ipv6_route").await {
+        for fields in table
+            .lines()
+            .map(|l| l.split_whitespace().collect::<Vec<_>>())
+        {
+            if fields.len() > 9 && fields[0] == any && fields[1] == "00" && fields[4] != any {
+                routes.push(format!("{} {}", fields[9], fields[4]));
+            }
+        }
+    }
+
+    routes.sort();
+    routes
+}
@@ -0,0 +1,64 @@
+/// Keeps the exporter alive; flushes pending spans when passed to [`shutdown`]
+#[cfg(feature = "otel")]
+pub struct OtelGuard(opentelemetry_sdk::trace::SdkTracerProvider);
+
+#[cfg(not(feature = "otel"))]
+pub struct OtelGuard;
+
+/// Export connection lifecycle spans over OTLP when `OTEL_EXPORTER_OTLP_ENDPOINT`
+/// is set. Tunnel code records `tracing` spans unconditionally; they cost
+/// nothing until this installs a subscriber.
+#[cfg(feature = "otel")]
+pub fn init() -> anyhow::Result<Option<OtelGuard>> {
+    use anyhow::Context;
+    use opentelemetry::trace::TracerProvider as _;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    let Ok(endpoint) = std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT") else {
+        return Ok(None);
+    };
+
+    // The exporter reads the endpoint and headers from the standard OTEL_* variables
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()
+        .context("Failed to create OTLP span exporter")?;
+
+    let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            opentelemetry_sdk::Resource::builder()
+                .with_service_name("m-tunnel")
+                .build(),
+        )
+        .build();
+
+    tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("m-tunnel")))
+        .try_init()
+        .context("Failed to install tracing subscriber")?;
+
+    log::info!("Exporting connection traces to {}", endpoint);
+    Ok(Some(OtelGuard(provider)))
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn init() -> anyhow::Result<Option<OtelGuard>> {
+    if std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").is_ok() {
+        log::warn!("OTEL_EXPORTER_OTLP_ENDPOINT is set but the otel feature is not enabled");
+    }
+    Ok(None)
+}
+
+/// Flush and stop the exporter
+pub fn shutdown(guard: Option<OtelGuard>) {
+    #[cfg(feature = "otel")]
+    if let Some(OtelGuard(provider)) = guard {
+        if let Err(e) = provider.shutdown() {
+            log::warn!("Failed to flush traces: {}", e);
+        }
+    }
+    #[cfg(not(feature = "otel"))]
+    let _ = guard;
+}
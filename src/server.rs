@@ -0,0 +1,587 @@
+use anyhow::Result;
+use log::info;
+use std::{
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
+
+use crate::config::Config;
+#[cfg(feature = "dashboard")]
+use crate::dashboard;
+use crate::metrics::MetricsCollector;
+use crate::tunnel_cli::{self, Controls};
+
+/// Serve the metrics and control API on `addr` and, when the configuration
+/// names one, on the control socket. Listeners that fail are restarted, so
+/// this runs until its task is dropped.
+pub async fn start_metrics_server(
+    metrics: Arc<MetricsCollector>,
+    config: Arc<RwLock<Config>>,
+    controls: Controls,
+    addr: Option<SocketAddr>,
+) -> Result<()> {
+    use warp::Filter;
+
+    let control_socket = config.read().unwrap().control_socket.clone();
+    #[cfg(feature = "tls")]
+    let tls = config.read().unwrap().metrics_tls.clone();
+    #[cfg(feature = "dashboard")]
+    let (dashboard_metrics, dashboard_config) = (Arc::clone(&metrics), Arc::clone(&config));
+
+    let health_metrics = Arc::clone(&metrics);
+    let events_metrics = Arc::clone(&metrics);
+    let influx_metrics = Arc::clone(&metrics);
+    let json_metrics = Arc::clone(&metrics);
+    let status_metrics = Arc::clone(&metrics);
+    let ready_metrics = Arc::clone(&metrics);
+    let supervisor_metrics = Arc::clone(&metrics);
+    let metrics_route = warp::path("metrics").map(move || {
+        warp::reply::with_header(
+            metrics.export_prometheus(),
+            "content-type",
+            "text/plain; version=0.0.4",
+        )
+    });
+
+    // Before /metrics, which would otherwise match this path too
+    let influx_route = warp::path!("metrics" / "influx").map(move || {
+        warp::reply::with_header(
+            influx_metrics.export_influx_line(),
+            "content-type",
+            "text/plain; charset=utf-8",
+        )
+    });
+
+    let json_route = warp::path!("metrics.json").map(move || {
+        warp::reply::json(&serde_json::json!({
+            "uptime_secs": json_metrics.uptime().as_secs(),
+            "tunnels": json_metrics.get_summary(),
+        }))
+    });
+
+    // Newline-delimited JSON, streamed until the client goes away. A client
+    // too slow to keep up skips the events it missed.
+    let events_route = warp::get()
+        .and(warp::path("events"))
+        .and(warp::path::end())
+        .map(move || {
+            use tokio_stream::StreamExt;
+
+            let stream =
+                tokio_stream::wrappers::BroadcastStream::new(events_metrics.subscribe_events())
+                    .filter_map(|event| event.ok())
+                    .map(|event| Ok::<_, std::convert::Infallible>(event + "\n"));
+            warp::http::Response::builder()
+                .header("content-type", "application/x-ndjson")
+                .body(warp::hyper::Body::wrap_stream(stream))
+        });
+
+    let health_route = warp::path("health").map(move || {
+        if health_metrics.is_healthy() {
+            warp::reply::with_status("OK", warp::http::StatusCode::OK)
+        } else {
+            warp::reply::with_status("DEGRADED", warp::http::StatusCode::SERVICE_UNAVAILABLE)
+        }
+    });
+
+    let group_route = group_route(Arc::clone(&config), controls.clone());
+    let status_route = status_route(Arc::clone(&config), status_metrics);
+    let ready_route = ready_route(ready_metrics);
+
+    // Key paths are shown and passphrases never serialized, but values taken
+    // from the environment can be secret too, so this is a control route
+    let config_route = warp::get()
+        .and(warp::path("config"))
+        .map(move || warp::reply::json(&redacted_config(&config.read().unwrap())));
+
+    let routes = influx_route
+        .or(json_route)
+        .or(metrics_route)
+        .or(health_route)
+        .or(ready_route)
+        .or(events_route)
+        .or(status_route);
+
+    // Routes that reveal the configuration or change tunnel state. Always
+    // served on the control socket, but on the metrics port only behind
+    // METRICS_TOKEN.
+    let control_routes = config_route.or(group_route);
+    #[cfg(feature = "chaos")]
+    let control_routes = control_routes.or(chaos_route(controls));
+    #[cfg(not(feature = "chaos"))]
+    drop(controls);
+
+    let token = metrics_token();
+    if token.is_none() && addr.is_some() {
+        info!("METRICS_TOKEN is not set, so /config and the control endpoints are only served on the control socket");
+    }
+
+    #[cfg(feature = "dashboard")]
+    let routes = routes.or(dashboard_route(dashboard_metrics, dashboard_config));
+
+    // The control socket is protected by its file permissions instead
+    let tcp_routes = bearer_auth(token.clone())
+        .and(
+            routes
+                .clone()
+                .or(with_token(token.is_some()).and(control_routes.clone())),
+        )
+        .recover(reject_unauthorized);
+    let routes = routes.or(control_routes);
+
+    let tcp = async {
+        let Some(addr) = addr else {
+            return;
+        };
+
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &tls {
+            info!("Starting metrics server on {} (HTTPS)", addr);
+            supervise("Metrics server", &supervisor_metrics, || async {
+                let (_, server) = warp::serve(tcp_routes.clone())
+                    .tls()
+                    .cert_path(&tls.cert_path)
+                    .key_path(&tls.key_path)
+                    .try_bind_with_graceful_shutdown(addr, std::future::pending())
+                    .map_err(|e| anyhow::anyhow!("Failed to bind {}: {}", addr, e))?;
+                server.await;
+                Err(anyhow::anyhow!("stopped"))
+            })
+            .await;
+            return;
+        }
+
+        info!("Starting metrics server on {}", addr);
+        supervise("Metrics server", &supervisor_metrics, || async {
+            let (_, server) = warp::serve(tcp_routes.clone())
+                .try_bind_ephemeral(addr)
+                .map_err(|e| anyhow::anyhow!("Failed to bind {}: {}", addr, e))?;
+            server.await;
+            Err(anyhow::anyhow!("stopped"))
+        })
+        .await;
+    };
+
+    let unix = async {
+        if let Some(path) = &control_socket {
+            supervise("Control socket", &supervisor_metrics, || {
+                serve_control_socket(routes.clone(), path)
+            })
+            .await;
+        }
+    };
+
+    tokio::join!(tcp, unix);
+    Ok(())
+}
+
+/// Rejection for TCP requests without the `METRICS_TOKEN` bearer token
+#[derive(Debug)]
+struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+/// The bearer token required on the metrics port, from `METRICS_TOKEN`
+fn metrics_token() -> Option<String> {
+    std::env::var("METRICS_TOKEN")
+        .ok()
+        .filter(|t| !t.is_empty())
+}
+
+/// Require `Authorization: Bearer <token>` when a token is set
+fn bearer_auth(
+    token: Option<String>,
+) -> impl warp::Filter<Extract = (), Error = warp::Rejection> + Clone {
+    use warp::Filter;
+
+    let token = token.map(Arc::new);
+    if token.is_some() {
+        info!("Metrics server requires a bearer token");
+    }
+
+    warp::header::optional::<String>("authorization")
+        .and_then(move |header: Option<String>| {
+            let token = token.clone();
+            async move {
+                let Some(token) = token else {
+                    return Ok(());
+                };
+                let presented = header
+                    .as_deref()
+                    .and_then(|h| h.strip_prefix("Bearer "))
+                    .unwrap_or_default();
+                if constant_time_eq(presented.as_bytes(), token.as_bytes()) {
+                    Ok(())
+                } else {
+                    Err(warp::reject::custom(Unauthorized))
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// Pass only when the metrics port requires a token; otherwise the routes
+/// behind this are not found on it
+fn with_token(required: bool) -> impl warp::Filter<Extract = (), Error = warp::Rejection> + Clone {
+    use warp::Filter;
+
+    warp::any()
+        .and_then(move || async move {
+            if required {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one()
+}
+
+/// The configuration as served by `GET /config`, with URLs cut down to
+/// scheme and host, as webhook and config URLs often carry tokens
+fn redacted_config(config: &Config) -> serde_json::Value {
+    let mut value = serde_json::to_value(config).unwrap_or_default();
+    for pointer in ["/config_url", "/heartbeat/webhook_url"] {
+        if let Some(url) = value.pointer_mut(pointer) {
+            if let Some(redacted) = url.as_str().map(redact_url) {
+                *url = serde_json::Value::String(redacted);
+            }
+        }
+    }
+    value
+}
+
+/// Keep a URL's scheme and host, dropping credentials, path and query
+fn redact_url(url: &str) -> String {
+    match url.split_once("://") {
+        Some((scheme, rest)) => {
+            let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+            let host = authority.rsplit('@').next().unwrap_or_default();
+            format!("{}://{}/<redacted>", scheme, host)
+        }
+        None => "<redacted>".to_string(),
+    }
+}
+
+/// Turn a missing or wrong token into 401; other rejections pass through
+async fn reject_unauthorized(
+    rejection: warp::Rejection,
+) -> std::result::Result<impl warp::Reply, warp::Rejection> {
+    if rejection.find::<Unauthorized>().is_some() {
+        Ok(warp::reply::with_header(
+            warp::reply::with_status("Unauthorized\n", warp::http::StatusCode::UNAUTHORIZED),
+            "www-authenticate",
+            "Bearer",
+        ))
+    } else {
+        Err(rejection)
+    }
+}
+
+/// Compare without returning early, so response time doesn't leak how much
+/// of the token matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Keep a listener running, restarting it with exponential backoff (up to
+/// a minute) whenever it fails or returns
+async fn supervise<F, Fut>(name: &str, metrics: &MetricsCollector, mut serve: F)
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<()>>,
+{
+    const MAX_BACKOFF: std::time::Duration = std::time::Duration::from_secs(60);
+    let mut backoff = std::time::Duration::from_secs(1);
+
+    loop {
+        let started = std::time::Instant::now();
+        let result = serve().await;
+
+        // A listener that ran for a while before failing starts over with a short delay
+        if started.elapsed() > MAX_BACKOFF {
+            backoff = std::time::Duration::from_secs(1);
+        }
+        match result {
+            Ok(()) => log::warn!("{} exited, restarting in {:?}", name, backoff),
+            Err(e) => log::warn!("{} failed: {:#}, restarting in {:?}", name, e, backoff),
+        }
+        metrics.record_metrics_server_restart();
+
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Serve the metrics routes on a Unix socket, readable by the owner only so
+/// filesystem permissions decide who may query and control tunnels
+#[cfg(unix)]
+async fn serve_control_socket<F>(routes: F, path: &std::path::Path) -> Result<()>
+where
+    F: warp::Filter + Clone + Send + Sync + 'static,
+    F::Extract: warp::Reply,
+{
+    use anyhow::Context;
+    use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+
+    // Replace a socket left behind by a previous run
+    if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        let _ = std::fs::remove_file(path);
+    }
+
+    // Bind inside a directory only we can enter and restrict the socket
+    // there, so it is never reachable with the umask's permissions, then
+    // move it into place
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("Control socket {} has no file name", path.display()))?;
+    let staging = path.with_file_name(format!(
+        ".{}.{}",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&staging);
+    let bound = std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&staging)
+        .map_err(anyhow::Error::from)
+        .and_then(|()| {
+            let staged = staging.join("socket");
+            let listener = tokio::net::UnixListener::bind(&staged)?;
+            std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+            std::fs::rename(&staged, path)?;
+            Ok(listener)
+        });
+    let _ = std::fs::remove_dir_all(&staging);
+    let listener =
+        bound.with_context(|| format!("Failed to bind control socket {}", path.display()))?;
+
+    info!("Serving metrics and control API on {}", path.display());
+    warp::serve(routes)
+        .run_incoming(tokio_stream::wrappers::UnixListenerStream::new(listener))
+        .await;
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn serve_control_socket<F>(_routes: F, _path: &std::path::Path) -> Result<()> {
+    Err(anyhow::anyhow!("control_socket is only supported on Unix"))
+}
+
+/// `POST /groups/{group}/{restart|pause|resume}` applies the operation to every
+/// enabled tunnel tagged with the group and returns the affected tunnel ids
+fn group_route(
+    config: Arc<RwLock<Config>>,
+    controls: Controls,
+) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    use tunnel_cli::TunnelControl;
+    use warp::{http::StatusCode, Filter};
+
+    let stagger =
+        std::time::Duration::from_millis(config.read().unwrap().limits.reconnect_stagger_ms);
+
+    warp::post()
+        .and(warp::path!("groups" / String / String))
+        .map(move |group: String, action: String| {
+            let config = config.read().unwrap();
+            let controls = controls.read().unwrap();
+            let members: Vec<(&str, &Arc<TunnelControl>)> = config
+                .tunnels
+                .iter()
+                .filter(|t| t.group.as_deref() == Some(group.as_str()))
+                .filter_map(|t| controls.get(&t.name).map(|c| (t.name.as_str(), c)))
+                .collect();
+
+            if members.is_empty() {
+                return warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({ "error": "unknown group" })),
+                    StatusCode::NOT_FOUND,
+                );
+            }
+
+            let locked: Vec<&str> = members
+                .iter()
+                .filter(|(_, c)| c.is_locked())
+                .map(|(id, _)| *id)
+                .collect();
+            if !locked.is_empty() {
+                return warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({
+                        "error": format!("tunnels are locked: {}", locked.join(", ")),
+                    })),
+                    StatusCode::FORBIDDEN,
+                );
+            }
+
+            if action == "restart" {
+                let cooling: Vec<_> = members
+                    .iter()
+                    .filter_map(|(id, c)| c.restart_cooldown_remaining().map(|r| (*id, r)))
+                    .collect();
+                if let Some(remaining) = cooling.iter().map(|(_, r)| *r).max() {
+                    let ids: Vec<&str> = cooling.iter().map(|(id, _)| *id).collect();
+                    return warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({
+                            "error": format!(
+                                "restarted too recently: {}",
+                                ids.join(", ")
+                            ),
+                            "retry_after_secs": remaining.as_secs_f64().ceil() as u64,
+                        })),
+                        StatusCode::TOO_MANY_REQUESTS,
+                    );
+                }
+            }
+
+            match action.as_str() {
+                "restart" => tunnel_cli::reconnect_all(
+                    members.iter().map(|(_, c)| Arc::clone(c)).collect(),
+                    stagger,
+                ),
+                "pause" => members.iter().for_each(|(_, c)| c.pause()),
+                "resume" => tunnel_cli::resume_all(
+                    members.iter().map(|(_, c)| Arc::clone(c)).collect(),
+                    stagger,
+                ),
+                _ => {
+                    return warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({ "error": "unknown operation" })),
+                        StatusCode::NOT_FOUND,
+                    )
+                }
+            }
+
+            let ids: Vec<&str> = members.iter().map(|(id, _)| *id).collect();
+            info!("Group '{}' {}: {}", group, action, ids.join(", "));
+            warp::reply::with_status(warp::reply::json(&ids), StatusCode::OK)
+        })
+}
+
+/// `GET /status` lists every configured tunnel with its live state, and answers
+/// 503 while any enabled tunnel is in error or disconnected
+fn status_route(
+    config: Arc<RwLock<Config>>,
+    metrics: Arc<MetricsCollector>,
+) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    use crate::metrics::{TunnelStats, TunnelStatus};
+    use warp::{http::StatusCode, Filter};
+
+    warp::get()
+        .and(warp::path("status"))
+        .and(warp::path::end())
+        .map(move || {
+            let config = config.read().unwrap();
+            let stats = metrics.get_summary();
+            let mut healthy = true;
+            let tunnels: Vec<_> = config
+                .tunnels
+                .iter()
+                .map(|tunnel| {
+                    // Tunnels that never reported have no stats yet
+                    let stat = stats.get(&tunnel.name).cloned().unwrap_or_default();
+                    if tunnel.enabled
+                        && matches!(
+                            stat.status,
+                            TunnelStatus::Error | TunnelStatus::Disconnected
+                        )
+                    {
+                        healthy = false;
+                    }
+                    let TunnelStats {
+                        status,
+                        reconnect_count,
+                        last_error,
+                        ..
+                    } = stat;
+                    serde_json::json!({
+                        "name": tunnel.name,
+                        "direction": tunnel.direction,
+                        "enabled": tunnel.enabled,
+                        "status": status,
+                        "reconnect_count": reconnect_count,
+                        "last_error": last_error,
+                    })
+                })
+                .collect();
+
+            let code = if healthy {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+            warp::reply::with_status(warp::reply::json(&tunnels), code)
+        })
+}
+
+/// `GET /healthz/ready` answers 200 once every critical tunnel's forward works
+/// and 503 until then. A bare `READY` or `NOT READY` suits probes. With
+/// `Accept: application/json`, the body lists each tunnel's part instead.
+fn ready_route(
+    metrics: Arc<MetricsCollector>,
+) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    use warp::{http::StatusCode, Filter, Reply};
+
+    warp::get()
+        .and(warp::path!("healthz" / "ready"))
+        .and(warp::header::optional::<String>("accept"))
+        .map(move |accept: Option<String>| {
+            let (ready, tunnels) = metrics.readiness();
+            let code = if ready {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+            if accept.is_some_and(|a| a.contains("application/json")) {
+                let body = serde_json::json!({ "ready": ready, "tunnels": tunnels });
+                warp::reply::with_status(warp::reply::json(&body), code).into_response()
+            } else {
+                let body = if ready { "READY" } else { "NOT READY" };
+                warp::reply::with_status(body, code).into_response()
+            }
+        })
+}
+
+/// `GET /` renders the HTML status page
+#[cfg(feature = "dashboard")]
+fn dashboard_route(
+    metrics: Arc<MetricsCollector>,
+    config: Arc<RwLock<Config>>,
+) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    use warp::Filter;
+
+    warp::get()
+        .and(warp::path::end())
+        .map(move || warp::reply::html(dashboard::render(&config.read().unwrap(), &metrics)))
+}
+
+/// `POST /tunnels/{id}/inject-failure` kills a tunnel's process to exercise
+/// the reconnect path. Needs both the `chaos` feature and `CHAOS=1`.
+#[cfg(feature = "chaos")]
+fn chaos_route(
+    controls: Controls,
+) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    use warp::{http::StatusCode, Filter};
+
+    let enabled = std::env::var("CHAOS").as_deref() == Ok("1");
+    if enabled {
+        log::warn!("Chaos endpoint enabled: POST /tunnels/{{id}}/inject-failure");
+    }
+
+    warp::post()
+        .and(warp::path!("tunnels" / String / "inject-failure"))
+        .map(move |id: String| match controls.read().unwrap().get(&id) {
+            Some(control) if enabled && control.is_locked() => warp::reply::with_status(
+                format!("Tunnel '{}' is locked\n", id),
+                StatusCode::FORBIDDEN,
+            ),
+            Some(control) if enabled => {
+                control.inject_failure();
+                log::warn!("Failure injected into tunnel '{}'", id);
+                warp::reply::with_status(
+                    format!("Failure injected into tunnel '{}'\n", id),
+                    StatusCode::ACCEPTED,
+                )
+            }
+            _ => warp::reply::with_status("Not found\n".to_string(), StatusCode::NOT_FOUND),
+        })
+}
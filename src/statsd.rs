@@ -0,0 +1,153 @@
+use std::sync::Arc;
+
+use crate::config::StatsdConfig;
+use crate::metrics::MetricsCollector;
+
+/// Largest datagram sent; metric lines are batched up to this size
+#[cfg(feature = "statsd")]
+const MAX_PACKET: usize = 1400;
+
+/// Push tunnel metrics to a StatsD or DogStatsD agent every
+/// `interval_secs`. Counters are sent as the change since the previous push.
+/// Sends are fire-and-forget: an unreachable agent only loses samples.
+#[cfg(feature = "statsd")]
+pub fn spawn(config: StatsdConfig, metrics: Arc<MetricsCollector>) {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use tokio::{net::UdpSocket, time};
+
+    log::info!("Pushing metrics to StatsD at {}", config.addr);
+
+    tokio::spawn(async move {
+        let mut interval = time::interval(Duration::from_secs(config.interval_secs.max(1)));
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+
+        let mut socket: Option<UdpSocket> = None;
+        let mut previous: HashMap<String, (u64, u64, u64, u64)> = HashMap::new();
+
+        loop {
+            interval.tick().await;
+
+            if socket.is_none() {
+                socket = connect(&config.addr).await;
+            }
+            let Some(sock) = &socket else {
+                continue;
+            };
+
+            let mut lines = Vec::new();
+            for (id, stat) in metrics.get_summary() {
+                let metric = |name: &str, value: String, kind: &str| {
+                    if config.dogstatsd {
+                        format!(
+                            "{}.{}:{}|{}|#tunnel:{}",
+                            config.prefix,
+                            name,
+                            value,
+                            kind,
+                            statsd_safe(&id)
+                        )
+                    } else {
+                        format!(
+                            "{}.tunnel.{}.{}:{}|{}",
+                            config.prefix,
+                            statsd_safe(&id).replace('.', "_"),
+                            name,
+                            value,
+                            kind
+                        )
+                    }
+                };
+
+                let current = (
+                    stat.reconnect_count,
+                    stat.forward_closed_count,
+                    stat.bytes_sent,
+                    stat.bytes_received,
+                );
+                let last = previous.insert(id.clone(), current).unwrap_or_default();
+
+                lines.push(metric("status", stat.status.code().to_string(), "g"));
+                lines.push(metric(
+                    "reconnects",
+                    current.0.saturating_sub(last.0).to_string(),
+                    "c",
+                ));
+                lines.push(metric(
+                    "forward_closed",
+                    current.1.saturating_sub(last.1).to_string(),
+                    "c",
+                ));
+                lines.push(metric(
+                    "bytes_sent",
+                    current.2.saturating_sub(last.2).to_string(),
+                    "c",
+                ));
+                lines.push(metric(
+                    "bytes_received",
+                    current.3.saturating_sub(last.3).to_string(),
+                    "c",
+                ));
+                if let Some(latency) = stat.connection_latency {
+                    lines.push(metric("latency", latency.as_millis().to_string(), "ms"));
+                }
+            }
+
+            let mut packet = String::new();
+            for line in lines {
+                if !packet.is_empty() && packet.len() + line.len() + 1 > MAX_PACKET {
+                    let _ = sock.send(packet.as_bytes()).await;
+                    packet.clear();
+                }
+                if !packet.is_empty() {
+                    packet.push('\n');
+                }
+                packet.push_str(&line);
+            }
+            if !packet.is_empty() {
+                let _ = sock.send(packet.as_bytes()).await;
+            }
+        }
+    });
+}
+
+/// A tunnel name made safe for a StatsD metric path or tag: characters the
+/// protocol reserves, such as `:`, `|`, `,`, `#` and newlines, become `_`
+#[cfg(feature = "statsd")]
+fn statsd_safe(id: &str) -> String {
+    id.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Resolve the agent address and open a UDP socket towards it. Retried on
+/// the next push when it fails.
+#[cfg(feature = "statsd")]
+async fn connect(addr: &str) -> Option<tokio::net::UdpSocket> {
+    let target = match tokio::net::lookup_host(addr).await {
+        Ok(mut addrs) => addrs.next()?,
+        Err(e) => {
+            log::debug!("Failed to resolve StatsD address {}: {}", addr, e);
+            return None;
+        }
+    };
+    let bind = if target.is_ipv6() {
+        "[::]:0"
+    } else {
+        "0.0.0.0:0"
+    };
+    let socket = tokio::net::UdpSocket::bind(bind).await.ok()?;
+    socket.connect(target).await.ok()?;
+    Some(socket)
+}
+
+#[cfg(not(feature = "statsd"))]
+pub fn spawn(_config: StatsdConfig, _metrics: Arc<MetricsCollector>) {
+    log::warn!("[statsd] is configured but the statsd feature is not enabled");
+}
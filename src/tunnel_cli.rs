@@ -1,18 +1,31 @@
 use anyhow::{Context, Result};
-use log::{error, info, warn};
+use log::{debug, error, info, warn};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
+    fmt,
     net::IpAddr,
+    path::PathBuf,
     process::Stdio,
     sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
     },
     time::{Duration, Instant},
 };
-use tokio::{process::Command, time};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    process::Command,
+    sync::mpsc,
+    task::JoinHandle,
+    time,
+};
+use tracing::Instrument;
 
-use crate::config::{Config, TunnelConfig};
+use crate::audit::{AuditEvent, AuditLog};
+use crate::config::{
+    AuthMethod, Config, ConnectionLimits, HostKeyPolicy, SshConfig, TunnelConfig, VerifyConfig,
+};
 use crate::metrics::{MetricsCollector, TunnelStatus};
 
 /// Get display name for server (use configured name or hide internal IPs)
@@ -27,8 +40,18 @@ fn get_server_display_name(ip_or_host: &str, server_name: &Option<String>) -> St
     }
 }
 
+/// Host as it goes into a `host:port` string: IPv6 literals in brackets, so
+/// their colons aren't read as the port separator by ssh or an HTTP server
+fn bracket_ipv6(host: &str) -> String {
+    if host.parse::<std::net::Ipv6Addr>().is_ok() {
+        format!("[{}]", host)
+    } else {
+        host.to_string()
+    }
+}
+
 /// Check if IP is a server internal network (hide completely)
-fn is_server_internal_ip(ip_or_host: &str) -> bool {
+pub fn is_server_internal_ip(ip_or_host: &str) -> bool {
     if let Ok(ip) = ip_or_host.parse::<IpAddr>() {
         match ip {
             IpAddr::V4(ipv4) => {
@@ -38,24 +61,149 @@ fn is_server_internal_ip(ip_or_host: &str) -> bool {
                     || (octets[0] == 10)
                     || (octets[0] == 172 && octets[1] >= 16 && octets[1] <= 31)
             }
-            IpAddr::V6(_) => false, // Assume IPv6 is not server internal for now
+            IpAddr::V6(ipv6) => {
+                let first = ipv6.segments()[0];
+                // Unique local fc00::/7 and link-local fe80::/10
+                (first & 0xfe00) == 0xfc00 || (first & 0xffc0) == 0xfe80
+            }
         }
     } else {
         false
     }
 }
+
+/// How often the forward of a running tunnel is probed
+const HEALTH_PROBE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Consecutive failed probes before the forward is considered closed by the gate
+const FORWARD_CLOSED_THRESHOLD: u32 = 3;
+
+/// How often a paused tunnel checks whether it was resumed
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often a send tunnel waiting for its local service checks again
+const LOCAL_SERVICE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often verification retries while the forward is not yet listening
+const VERIFY_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How often a new session's local listener is polled to time the connect
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long tunnels stopped by a reload get to close their ssh process
+/// before their task is aborted
+const STOP_GRACE: Duration = Duration::from_secs(5);
+
+/// How long after spawning ssh stderr output is treated as the gate's login banner
+const BANNER_WINDOW: Duration = Duration::from_secs(10);
+
+/// A gate message that asks clients to slow down, and the minimum delay
+/// before the next attempt once it has been seen
+#[derive(Debug, PartialEq, Eq)]
+pub struct ThrottleHint {
+    pub name: &'static str,
+    prefix: &'static str,
+    pattern: &'static str,
+    pub backoff: Duration,
+}
+
+impl ThrottleHint {
+    fn matches(&self, line: &str) -> bool {
+        line.starts_with(self.prefix) && line.contains(self.pattern)
+    }
+}
+
+const THROTTLE_HINTS: &[ThrottleHint] = &[
+    // sshd dropping us after too many keys were offered
+    ThrottleHint {
+        name: "too_many_auth_failures",
+        prefix: "",
+        pattern: "Too many authentication failures",
+        backoff: Duration::from_secs(300),
+    },
+    // MaxStartups throttling closes new connections before the key exchange
+    ThrottleHint {
+        name: "max_startups",
+        prefix: "",
+        pattern: "kex_exchange_identification",
+        backoff: Duration::from_secs(60),
+    },
+    // The gate refusing the ssh connection itself, not a forwarded channel's
+    // "connect failed: Connection refused" when the target service is down
+    ThrottleHint {
+        name: "connection_refused",
+        prefix: "ssh: connect to host ",
+        pattern: "Connection refused",
+        backoff: Duration::from_secs(30),
+    },
+];
+
+/// Failure modes of a tunnel process that are reported distinctly from a plain disconnect
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TunnelFailure {
+    /// The SSH session is alive but the gate no longer carries the forward
+    ForwardClosed,
+    /// The gate rejected our credentials
+    AuthFailure,
+    /// The gate told us to slow down
+    Throttled(&'static ThrottleHint),
+    /// The forwarded service did not answer as the tunnel's verify block expects
+    VerificationFailed,
+}
+
+impl fmt::Display for TunnelFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TunnelFailure::ForwardClosed => {
+                write!(f, "Forward closed by gate while the session stayed alive")
+            }
+            TunnelFailure::AuthFailure => write!(f, "Authentication rejected by gate"),
+            TunnelFailure::Throttled(hint) => write!(
+                f,
+                "Gate asked to slow down ({}), backing off at least {}s",
+                hint.name,
+                hint.backoff.as_secs()
+            ),
+            TunnelFailure::VerificationFailed => {
+                write!(f, "Forwarded service failed post-connect verification")
+            }
+        }
+    }
+}
+
+impl TunnelFailure {
+    /// Short machine-readable name, used as the failure kind in traces
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TunnelFailure::ForwardClosed => "forward_closed",
+            TunnelFailure::AuthFailure => "auth_failure",
+            TunnelFailure::Throttled(_) => "throttled",
+            TunnelFailure::VerificationFailed => "verification_failed",
+        }
+    }
+}
+
+impl std::error::Error for TunnelFailure {}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TunnelDirection {
     Send,    // Local push (SSH -R) - push local service to remote server
     Receive, // Remote pull (SSH -L) - pull remote service to local
+    Dynamic, // SOCKS proxy (SSH -D) - reach any host through the gate
 }
 
-impl From<&str> for TunnelDirection {
-    fn from(s: &str) -> Self {
+impl std::str::FromStr for TunnelDirection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
         match s {
-            "send" => TunnelDirection::Send,
-            "receive" => TunnelDirection::Receive,
-            _ => panic!("Invalid tunnel direction: {}", s),
+            "send" => Ok(TunnelDirection::Send),
+            "receive" => Ok(TunnelDirection::Receive),
+            "dynamic" => Ok(TunnelDirection::Dynamic),
+            _ => Err(anyhow::anyhow!(
+                "invalid direction {:?}, expected \"send\", \"receive\" or \"dynamic\"",
+                s
+            )),
         }
     }
 }
@@ -63,23 +211,72 @@ impl From<&str> for TunnelDirection {
 #[derive(Debug, Clone)]
 pub struct Tunnel {
     pub id: String,
+    pub log_name: String,
     pub direction: TunnelDirection,
     pub local_host: String,
     pub local_port: u16,
     pub remote_host: String,
     pub remote_port: u16,
+    pub require_local_service: bool,
+    pub degraded_reconnects: Option<u32>,
+    pub degraded_window: Duration,
+    pub degraded_fails_health: bool,
+    pub dscp: Option<u8>,
+    pub env: HashMap<String, String>,
+    pub verify: Option<VerifyConfig>,
+    pub gate: Option<String>,
+    pub key_path: Option<PathBuf>,
 }
 
-impl From<&TunnelConfig> for Tunnel {
-    fn from(config: &TunnelConfig) -> Self {
-        Self {
+impl Tunnel {
+    /// Filesystem path when the local end is a Unix socket (`local_host = "unix:/path"`)
+    fn local_socket_path(&self) -> Option<&str> {
+        self.local_host.strip_prefix("unix:")
+    }
+
+    /// Address to reach a receive tunnel's local listener on
+    fn connect_host(&self) -> &str {
+        match self.local_host.as_str() {
+            "0.0.0.0" | "" => "127.0.0.1",
+            "::" => "::1",
+            host => host,
+        }
+    }
+
+    /// Local end of the forward as ssh expects it: a socket path or `host:port`
+    fn local_endpoint(&self) -> String {
+        match self.local_socket_path() {
+            Some(path) => path.to_string(),
+            None => format!("{}:{}", bracket_ipv6(&self.local_host), self.local_port),
+        }
+    }
+}
+
+impl TryFrom<&TunnelConfig> for Tunnel {
+    type Error = anyhow::Error;
+
+    fn try_from(config: &TunnelConfig) -> Result<Self> {
+        Ok(Self {
             id: config.name.clone(),
-            direction: TunnelDirection::from(config.direction.as_str()),
+            log_name: config.log_name().to_string(),
+            direction: config
+                .direction
+                .parse()
+                .with_context(|| format!("Tunnel '{}'", config.name))?,
             local_host: config.local_host.clone(),
             local_port: config.local_port,
             remote_host: config.remote_host.clone(),
             remote_port: config.remote_port,
-        }
+            require_local_service: config.require_local_service,
+            dscp: config.dscp,
+            env: config.env.clone(),
+            verify: config.verify.clone(),
+            gate: config.gate.clone(),
+            key_path: config.key_path.clone(),
+            degraded_reconnects: config.degraded_reconnects,
+            degraded_window: Duration::from_secs(config.degraded_window_secs),
+            degraded_fails_health: config.degraded_fails_health,
+        })
     }
 }
 
@@ -133,32 +330,483 @@ impl ConnectionLimiter {
             }
         }
     }
+
+    /// How long until `can_attempt` will next succeed for this host
+    fn time_until_available(&self, host: &str) -> Duration {
+        match self.attempts.get(host) {
+            Some((count, time)) if *count >= self.max_attempts => {
+                self.window.saturating_sub(time.elapsed())
+            }
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+/// Up to `max` of pseudo-random delay, so tunnels released together don't
+/// all reconnect in the same instant
+fn jitter(max: Duration) -> Duration {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    max.mul_f64(f64::from(nanos) / 1e9)
+}
+
+/// Per-tunnel switches flipped from outside the tunnel's task
+#[derive(Debug, Default)]
+pub struct TunnelControl {
+    reconnect: AtomicBool,
+    fail: AtomicBool,
+    paused: AtomicBool,
+    locked: AtomicBool,
+    stopped: AtomicBool,
+    wake: tokio::sync::Notify,
+    restart_cooldown: Duration,
+    last_restart: Mutex<Option<Instant>>,
+}
+
+impl TunnelControl {
+    pub fn new(restart_cooldown: Duration, locked: bool) -> Self {
+        Self {
+            restart_cooldown,
+            locked: AtomicBool::new(locked),
+            ..Default::default()
+        }
+    }
+
+    /// Locked tunnels refuse restart, pause and resume through the control API
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    /// Ask the tunnel to drop its current connection and reconnect right away
+    pub fn request_reconnect(&self) {
+        *self.last_restart.lock().unwrap() = Some(Instant::now());
+        self.reconnect.store(true, Ordering::Relaxed);
+        self.wake();
+    }
+
+    /// Interrupt the tunnel's wait so it re-checks its switches and shutdown
+    pub fn wake(&self) {
+        self.wake.notify_one();
+    }
+
+    async fn woken(&self) {
+        self.wake.notified().await;
+    }
+
+    /// Time left before another restart is allowed, if the tunnel was
+    /// restarted within `restart_cooldown_secs`. The control API answers a
+    /// restart with 429 while this is `Some`:
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use m_tunnel::tunnel_cli::TunnelControl;
+    ///
+    /// let control = TunnelControl::new(Duration::from_secs(30), false);
+    /// assert_eq!(control.restart_cooldown_remaining(), None);
+    ///
+    /// control.request_reconnect();
+    /// assert!(control.restart_cooldown_remaining().unwrap() > Duration::from_secs(29));
+    ///
+    /// // Without a cooldown, restarts are never held back
+    /// let control = TunnelControl::new(Duration::ZERO, false);
+    /// control.request_reconnect();
+    /// assert_eq!(control.restart_cooldown_remaining(), None);
+    /// ```
+    pub fn restart_cooldown_remaining(&self) -> Option<Duration> {
+        let last = (*self.last_restart.lock().unwrap())?;
+        let remaining = self.restart_cooldown.saturating_sub(last.elapsed());
+        (!remaining.is_zero()).then_some(remaining)
+    }
+
+    fn take_reconnect_request(&self) -> bool {
+        self.reconnect.swap(false, Ordering::Relaxed)
+    }
+
+    /// Drop the tunnel's connection and keep it down until resumed
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+        self.wake();
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Kill the tunnel's process and report it as a failure, for chaos testing
+    #[cfg(feature = "chaos")]
+    pub fn inject_failure(&self) {
+        self.fail.store(true, Ordering::Relaxed);
+        self.wake();
+    }
+
+    fn take_injected_failure(&self) -> bool {
+        self.fail.swap(false, Ordering::Relaxed)
+    }
+
+    /// End the tunnel's task for good, for tunnels removed or changed by a reload
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        self.wake();
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+}
+
+/// Control handles of the running tunnels, keyed by tunnel name. Shared with
+/// the control API and updated when the configuration is reloaded.
+pub type Controls = Arc<RwLock<HashMap<String, Arc<TunnelControl>>>>;
+
+/// Ask every tunnel to reconnect, spread evenly over `window` so a bulk
+/// reconnect doesn't hit the gates all at once:
+///
+/// ```
+/// use std::{sync::Arc, time::Duration};
+/// use m_tunnel::tunnel_cli::{reconnect_all, TunnelControl};
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let controls: Vec<_> = (0..3)
+///     .map(|_| Arc::new(TunnelControl::new(Duration::from_secs(60), false)))
+///     .collect();
+/// reconnect_all(controls.clone(), Duration::from_millis(600));
+/// tokio::time::sleep(Duration::from_millis(800)).await;
+///
+/// // The reconnects were requested about 200ms apart
+/// let cooldowns: Vec<Duration> = controls
+///     .iter()
+///     .map(|c| c.restart_cooldown_remaining().unwrap())
+///     .collect();
+/// assert!(cooldowns[1] >= cooldowns[0] + Duration::from_millis(150));
+/// assert!(cooldowns[2] >= cooldowns[1] + Duration::from_millis(150));
+/// # }
+/// ```
+pub fn reconnect_all(controls: Vec<Arc<TunnelControl>>, window: Duration) {
+    staggered(controls, window, |control| {
+        match control.restart_cooldown_remaining() {
+            Some(remaining) => debug!(
+                "Skipping reconnect, restart cooldown has {:.0}s left",
+                remaining.as_secs_f64()
+            ),
+            None => control.request_reconnect(),
+        }
+    });
+}
+
+/// Resume paused tunnels, spread over `window` like [`reconnect_all`]
+pub fn resume_all(controls: Vec<Arc<TunnelControl>>, window: Duration) {
+    staggered(controls, window, |control| control.resume());
+}
+
+/// Apply `action` to each tunnel in turn, spread evenly over `window`
+fn staggered<F>(controls: Vec<Arc<TunnelControl>>, window: Duration, action: F)
+where
+    F: Fn(&TunnelControl) + Send + 'static,
+{
+    if controls.is_empty() {
+        return;
+    }
+
+    let step = window / controls.len() as u32;
+    tokio::spawn(async move {
+        for (i, control) in controls.iter().enumerate() {
+            if i > 0 {
+                time::sleep(step).await;
+            }
+            action(control);
+        }
+    });
+}
+
+/// Smooth weighted round-robin over the configured gates, shared by all tunnels
+/// so connections spread across gates in proportion to their weights.
+#[derive(Debug)]
+struct GateSelector {
+    gates: Vec<SshConfig>,
+    current: Vec<i64>,
+}
+
+// The pinned known_hosts files belong to this process and go with the gates
+impl Drop for GateSelector {
+    fn drop(&mut self) {
+        remove_pinned_known_hosts(&self.gates);
+    }
+}
+
+impl GateSelector {
+    fn new(gates: Vec<SshConfig>) -> Self {
+        let current = vec![0; gates.len()];
+        Self { gates, current }
+    }
+
+    /// The gate a tunnel names, or the next gate of the pool, set up with
+    /// the tunnel's own key when it has one
+    fn pick(&mut self, tunnel: &Tunnel, avoid: Option<usize>) -> (usize, SshConfig) {
+        let named = tunnel.gate.as_deref().and_then(|name| {
+            let index = self
+                .gates
+                .iter()
+                .position(|g| g.name.as_deref() == Some(name));
+            // validate() rules this out; never let it pass unnoticed
+            if index.is_none() {
+                error!(
+                    "Tunnel '{}' names gate '{}', which is not configured; connecting through the gate pool instead",
+                    tunnel.log_name, name
+                );
+            }
+            index
+        });
+        let (index, mut gate) = match named {
+            Some(index) => (index, self.gates[index].clone()),
+            None => self.next(avoid),
+        };
+        if let Some(key_path) = &tunnel.key_path {
+            gate.key_path = key_path.clone();
+        }
+        (index, gate)
+    }
+
+    /// Pick the next gate, skipping `avoid` when another gate is available
+    fn next(&mut self, avoid: Option<usize>) -> (usize, SshConfig) {
+        let total: i64 = self.gates.iter().map(|g| i64::from(g.weight)).sum();
+        let mut best = 0;
+
+        for _ in 0..self.gates.len() {
+            for (current, gate) in self.current.iter_mut().zip(&self.gates) {
+                *current += i64::from(gate.weight);
+            }
+            best = (0..self.current.len())
+                .max_by_key(|&i| (self.current[i], std::cmp::Reverse(i)))
+                .unwrap_or(0);
+            self.current[best] -= total;
+
+            if self.gates.len() == 1 || Some(best) != avoid {
+                break;
+            }
+        }
+
+        (best, self.gates[best].clone())
+    }
+}
+
+/// A gate's identity across reloads: its name, or its label when unnamed
+fn gate_id(gate: &SshConfig) -> String {
+    gate.name.clone().unwrap_or_else(|| gate.label())
+}
+
+/// Whether two versions of a gate connect with the same ssh settings. The
+/// weight only steers gate selection, so it is left out.
+fn same_gate(old: &SshConfig, new: &SshConfig) -> bool {
+    gate_changes(old, new).is_empty()
+}
+
+/// Names of the ssh settings that differ between two versions of a gate
+fn gate_changes(old: &SshConfig, new: &SshConfig) -> Vec<String> {
+    let mut changed = crate::config::changed_fields(old, new);
+    changed.retain(|field| field != "weight");
+    // Never serialized, so compared on its own
+    if old.key_passphrase != new.key_passphrase {
+        changed.push("key_passphrase".to_string());
+    }
+    changed
+}
+
+/// Handles shared by every tunnel task of a manager
+#[derive(Clone)]
+struct TunnelContext {
+    limits: ConnectionLimits,
+    gate_selector: Arc<Mutex<GateSelector>>,
+    metrics: Arc<MetricsCollector>,
+    connection_limiter: Arc<Mutex<ConnectionLimiter>>,
+    audit: Arc<AuditLog>,
+    shutdown: Arc<AtomicBool>,
+}
+
+/// A spawned tunnel task and the configuration it was started from
+struct RunningTunnel {
+    config: TunnelConfig,
+    control: Arc<TunnelControl>,
+    handle: JoinHandle<Result<()>>,
 }
 
 pub struct TunnelManager {
     config: Config,
+    tunnels: Arc<RwLock<Vec<TunnelConfig>>>, // Current tunnel list, replaced on reload
     metrics: Arc<MetricsCollector>,
     connection_limiter: Arc<Mutex<ConnectionLimiter>>,
+    gate_selector: Arc<Mutex<GateSelector>>,
+    audit: Arc<AuditLog>,
+    controls: Controls,
+    reload_tx: mpsc::UnboundedSender<Config>,
+    reload_rx: Mutex<Option<mpsc::UnboundedReceiver<Config>>>,
     pub shutdown: Arc<AtomicBool>,
 }
 
 impl TunnelManager {
+    /// Prepare the gates and tunnels of `config`. Fails when the configuration
+    /// is invalid or cannot be put into effect: an unusable known_hosts file or certificate,
+    /// a gate host key that doesn't match its pinned fingerprint, or an audit
+    /// log that can't be opened.
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    ///
+    /// use m_tunnel::{Config, MetricsCollector, TunnelManager};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let config: Config = toml::from_str(
+    ///     r#"
+    ///     [gate]
+    ///     host = "gate.example.com"
+    ///     user = "tunnel"
+    ///     key_path = "/etc/m-tunnel/key"
+    ///     timeout = 10
+    ///     keepalive_interval = 30
+    ///
+    ///     [limits]
+    ///     max_attempts = 5
+    ///     retry_window_secs = 300
+    ///     max_backoff_secs = 60
+    ///
+    ///     [[tunnels]]
+    ///     name = "web"
+    ///     direction = "sideways"
+    ///     local_host = "127.0.0.1"
+    ///     local_port = 8080
+    ///     remote_host = "127.0.0.1"
+    ///     remote_port = 80
+    ///     enabled = true
+    ///     "#,
+    /// )
+    /// .unwrap();
+    ///
+    /// let error = TunnelManager::new(config, Arc::new(MetricsCollector::new()))
+    ///     .await
+    ///     .err()
+    ///     .unwrap();
+    /// assert!(format!("{:#}", error).contains("invalid direction"));
+    /// # }
+    /// ```
     pub async fn new(config: Config, metrics: Arc<MetricsCollector>) -> Result<Self> {
         info!("Initializing tunnel manager");
+        config.validate()?;
 
         let connection_limiter = Arc::new(Mutex::new(ConnectionLimiter::new(
             config.limits.max_attempts,
             Duration::from_secs(config.limits.retry_window_secs),
         )));
 
+        let gates = Self::prepare_gates(config.gates(), &metrics).await?;
+        let gate_selector = Arc::new(Mutex::new(GateSelector::new(gates)));
+        let audit = Arc::new(AuditLog::new(config.audit.as_ref(), metrics.events())?);
+
+        for tunnel in config.tunnels.iter().filter(|t| t.enabled) {
+            metrics.register_tunnel(&tunnel.name);
+        }
+
+        let restart_cooldown = Duration::from_secs(config.limits.restart_cooldown_secs);
+        let controls = config
+            .tunnels
+            .iter()
+            .filter(|t| t.enabled)
+            .map(|t| {
+                (
+                    t.name.clone(),
+                    Arc::new(TunnelControl::new(restart_cooldown, t.locked)),
+                )
+            })
+            .collect();
+        let (reload_tx, reload_rx) = mpsc::unbounded_channel();
+
         Ok(Self {
+            tunnels: Arc::new(RwLock::new(config.tunnels.clone())),
             config,
             metrics,
             connection_limiter,
+            gate_selector,
+            audit,
+            controls: Arc::new(RwLock::new(controls)),
+            reload_tx,
+            reload_rx: Mutex::new(Some(reload_rx)),
             shutdown: Arc::new(AtomicBool::new(false)),
         })
     }
 
+    /// Check the gates and resolve what they need at connect time: known_hosts
+    /// files, certificates, pinned addresses and host keys. Fails, leaving no
+    /// pinned known_hosts file behind, when a gate cannot be used.
+    async fn prepare_gates(
+        gates: &[SshConfig],
+        metrics: &MetricsCollector,
+    ) -> Result<Vec<SshConfig>> {
+        for gate in gates {
+            if let Some(path) = gate.known_hosts_path.as_deref() {
+                prepare_known_hosts(path, gate.host_key_checking())?;
+            }
+        }
+
+        if gates.iter().any(|g| g.auth_method == AuthMethod::Agent)
+            && std::env::var_os("SSH_AUTH_SOCK").is_none()
+        {
+            warn!("A gate uses auth_method agent but SSH_AUTH_SOCK is not set; ssh will only find an agent configured in ~/.ssh/config");
+        }
+
+        for gate in gates {
+            if let Some(cert) = &gate.cert_path {
+                check_certificate(gate, cert).await?;
+            }
+        }
+
+        let mut gates = gates.to_vec();
+        for gate in gates.iter_mut().filter(|g| g.pin_gate_ip) {
+            gate.pinned_ip = resolve_gate(gate).await;
+            if let Some(ip) = gate.pinned_ip {
+                metrics.record_gate_pinned_ip(&gate.label(), ip);
+            }
+        }
+
+        for index in 0..gates.len() {
+            if let Some(fingerprint) = gates[index].host_key_fingerprint.clone() {
+                match pin_host_key(&gates[index], &fingerprint).await {
+                    Ok(path) => gates[index].pinned_known_hosts = Some(path),
+                    Err(e) => {
+                        remove_pinned_known_hosts(&gates);
+                        return Err(e);
+                    }
+                }
+            }
+        }
+
+        for gate in gates.iter().filter(|g| g.preflight_checks) {
+            Self::preflight(gate).await;
+        }
+
+        Ok(gates)
+    }
+
+    /// Control handles of the enabled tunnels, keyed by tunnel name
+    pub fn controls(&self) -> Controls {
+        Arc::clone(&self.controls)
+    }
+
+    /// Sender for reloaded configurations. The running manager restarts only
+    /// the tunnels whose configuration changed, starts new ones and stops the
+    /// ones that were removed or disabled. Gate changes reconnect the tunnels
+    /// using the changed gates, and new `max_attempts` or `retry_window_secs`
+    /// rebuild the connection limiter. Other settings need a new manager. An
+    /// invalid configuration is logged and ignored.
+    pub fn reloader(&self) -> mpsc::UnboundedSender<Config> {
+        self.reload_tx.clone()
+    }
+
     pub async fn start(&self) -> Result<()> {
         info!(
             "Starting tunnel manager: {} configured tunnels",
@@ -170,44 +818,377 @@ impl TunnelManager {
         // Start status monitoring task
         let status_metrics = Arc::clone(&self.metrics);
         let status_config = self.config.clone();
+        let status_tunnels = Arc::clone(&self.tunnels);
         let status_shutdown = Arc::clone(&self.shutdown);
         handles.push(tokio::spawn(async move {
-            Self::monitor_tunnel_status(status_metrics, status_config, status_shutdown).await;
+            Self::monitor_tunnel_status(
+                status_metrics,
+                status_config,
+                status_tunnels,
+                status_shutdown,
+            )
+            .await;
         }));
 
+        if self.config.limits.reconnect_on_network_change {
+            let controls = Arc::clone(&self.controls);
+            let stagger = Duration::from_millis(self.config.limits.reconnect_stagger_ms);
+            handles.push(tokio::spawn(async move {
+                crate::netwatch::watch_default_route(|| {
+                    reconnect_all(
+                        controls.read().unwrap().values().cloned().collect(),
+                        stagger,
+                    );
+                })
+                .await;
+            }));
+        }
+
+        let context = TunnelContext {
+            limits: self.config.limits.clone(),
+            gate_selector: Arc::clone(&self.gate_selector),
+            metrics: Arc::clone(&self.metrics),
+            connection_limiter: Arc::clone(&self.connection_limiter),
+            audit: Arc::clone(&self.audit),
+            shutdown: Arc::clone(&self.shutdown),
+        };
+        let mut running = HashMap::new();
+
         for tunnel_config in &self.config.tunnels {
             if !tunnel_config.enabled {
-                info!("Skipping disabled tunnel: {}", tunnel_config.name);
+                info!("Skipping disabled tunnel: {}", tunnel_config.log_name());
                 continue;
             }
 
-            let tunnel = Tunnel::from(tunnel_config);
-            let ssh_config = self.config.gate.clone();
-            let metrics = Arc::clone(&self.metrics);
-            let limiter = Arc::clone(&self.connection_limiter);
-            let shutdown = Arc::clone(&self.shutdown);
-
-            handles.push(tokio::spawn(async move {
-                Self::manage_ssh_cli_tunnel(tunnel, ssh_config, metrics, limiter, shutdown).await;
-            }));
+            let control = Arc::clone(&self.controls.read().unwrap()[&tunnel_config.name]);
+            running.insert(
+                tunnel_config.name.clone(),
+                self.spawn_tunnel(tunnel_config, control, &context)?,
+            );
         }
 
-        // Wait for shutdown signal
+        let mut reload_rx = self.reload_rx.lock().unwrap().take();
+
+        // Wait for shutdown signal or a tunnel giving up for good, applying
+        // reloaded configurations in between
+        let mut result = Ok(());
         while !self.shutdown.load(Ordering::Relaxed) {
-            time::sleep(Duration::from_secs(1)).await;
+            let reload = async {
+                match reload_rx.as_mut() {
+                    Some(rx) => rx.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = time::sleep(Duration::from_secs(1)) => {}
+                Some(config) = reload => {
+                    self.apply_reload(&mut running, config, &context).await;
+                }
+            }
+
+            let finished = running
+                .iter()
+                .find(|(_, t)| t.handle.is_finished())
+                .map(|(name, _)| name.clone());
+            if let Some(name) = finished {
+                let tunnel = running.remove(&name).expect("finished tunnel is running");
+                if let Ok(Err(e)) = tunnel.handle.await {
+                    self.shutdown.store(true, Ordering::Relaxed);
+                    self.wake_all();
+                    result = Err(e);
+                }
+            }
         }
 
         // Cancel all tunnel tasks
         for handle in handles {
             handle.abort();
         }
+        for tunnel in running.into_values() {
+            tunnel.handle.abort();
+        }
 
-        Ok(())
+        result
+    }
+
+    fn spawn_tunnel(
+        &self,
+        tunnel_config: &TunnelConfig,
+        control: Arc<TunnelControl>,
+        context: &TunnelContext,
+    ) -> Result<RunningTunnel> {
+        let tunnel = Tunnel::try_from(tunnel_config)?;
+        if let Some(dscp) = tunnel.dscp {
+            self.metrics.record_dscp(&tunnel.id, dscp);
+        }
+        let context = context.clone();
+        let task_control = Arc::clone(&control);
+
+        Ok(RunningTunnel {
+            config: tunnel_config.clone(),
+            control,
+            handle: tokio::spawn(async move {
+                Self::manage_ssh_cli_tunnel(tunnel, context, task_control).await
+            }),
+        })
+    }
+
+    /// Bring the running tunnels in line with a reloaded configuration. Tunnels
+    /// whose configuration is unchanged keep their ssh process, unless the gate
+    /// they are connected through changed.
+    async fn apply_reload(
+        &self,
+        running: &mut HashMap<String, RunningTunnel>,
+        config: Config,
+        context: &TunnelContext,
+    ) {
+        if let Err(e) = config.validate() {
+            error!(
+                "Reload rejected, keeping the running configuration: {:#}",
+                e
+            );
+            return;
+        }
+        self.reload_limiter(&config.limits);
+        let retired = match self.reload_gates(config.gates()).await {
+            Ok(retired) => retired,
+            Err(e) => {
+                error!("Gates not reloaded, keeping the running gates: {:#}", e);
+                Vec::new()
+            }
+        };
+
+        let tunnels = config.tunnels;
+        let wanted: HashMap<&str, &TunnelConfig> = tunnels
+            .iter()
+            .filter(|t| t.enabled)
+            .map(|t| (t.name.as_str(), t))
+            .collect();
+
+        let stale: Vec<String> = running
+            .iter()
+            .filter(|(name, t)| wanted.get(name.as_str()) != Some(&&t.config))
+            .map(|(name, _)| name.clone())
+            .collect();
+        let (changed, removed): (Vec<String>, Vec<String>) = stale
+            .into_iter()
+            .partition(|name| wanted.contains_key(name.as_str()));
+
+        let stopping: Vec<RunningTunnel> = changed
+            .iter()
+            .chain(&removed)
+            .filter_map(|name| running.remove(name))
+            .collect();
+        Self::stop_tunnels(stopping).await;
+
+        {
+            let mut controls = self.controls.write().unwrap();
+            for name in &removed {
+                controls.remove(name);
+                self.metrics.remove_tunnel(name);
+            }
+        }
+
+        // Unchanged tunnels connected through a gate that changed or went away
+        let stats = self.metrics.get_summary();
+        let regated: Vec<String> = running
+            .keys()
+            .filter(|name| {
+                stats
+                    .get(*name)
+                    .and_then(|s| s.gate.as_ref())
+                    .is_some_and(|gate| retired.contains(gate))
+            })
+            .cloned()
+            .collect();
+
+        let restart_cooldown = Duration::from_secs(self.config.limits.restart_cooldown_secs);
+        let stagger = Duration::from_millis(self.config.limits.reconnect_stagger_ms);
+        if !regated.is_empty() {
+            info!(
+                "Reconnecting tunnels whose gate changed: {}",
+                regated.join(", ")
+            );
+            staggered(
+                regated
+                    .iter()
+                    .map(|name| Arc::clone(&running[name].control))
+                    .collect(),
+                stagger,
+                |control| control.request_reconnect(),
+            );
+        }
+
+        let starting: Vec<&TunnelConfig> = tunnels
+            .iter()
+            .filter(|t| t.enabled && !running.contains_key(&t.name))
+            .collect();
+        // Spread the new connections over the stagger window like a bulk reconnect
+        let step = stagger / starting.len().max(1) as u32;
+        let (mut added, mut restarted) = (0, 0);
+        for (i, tunnel_config) in starting.into_iter().enumerate() {
+            if i > 0 {
+                time::sleep(step).await;
+            }
+
+            self.metrics.register_tunnel(&tunnel_config.name);
+            let control = Arc::new(TunnelControl::new(restart_cooldown, tunnel_config.locked));
+            let tunnel = match self.spawn_tunnel(tunnel_config, Arc::clone(&control), context) {
+                Ok(tunnel) => tunnel,
+                Err(e) => {
+                    error!("Not starting tunnel: {:#}", e);
+                    self.metrics.remove_tunnel(&tunnel_config.name);
+                    continue;
+                }
+            };
+            if changed.contains(&tunnel_config.name) {
+                restarted += 1;
+            } else {
+                added += 1;
+            }
+            self.controls
+                .write()
+                .unwrap()
+                .insert(tunnel_config.name.clone(), control);
+            running.insert(tunnel_config.name.clone(), tunnel);
+        }
+
+        info!(
+            "Configuration reloaded: {} added, {} restarted, {} reconnected, {} stopped, {} unchanged",
+            added,
+            restarted,
+            regated.len(),
+            removed.len(),
+            running.len() - added - restarted - regated.len()
+        );
+        *self.tunnels.write().unwrap() = tunnels;
+    }
+
+    /// Rebuild the connection limiter when `max_attempts` or
+    /// `retry_window_secs` changed. The attempts counted so far go with it.
+    fn reload_limiter(&self, limits: &ConnectionLimits) {
+        let window = Duration::from_secs(limits.retry_window_secs);
+        let mut limiter = self.connection_limiter.lock().unwrap();
+        if limiter.max_attempts == limits.max_attempts && limiter.window == window {
+            return;
+        }
+
+        let mut changes = Vec::new();
+        if limiter.max_attempts != limits.max_attempts {
+            changes.push(format!(
+                "max_attempts {} -> {}",
+                limiter.max_attempts, limits.max_attempts
+            ));
+        }
+        if limiter.window != window {
+            changes.push(format!(
+                "retry_window_secs {} -> {}",
+                limiter.window.as_secs(),
+                limits.retry_window_secs
+            ));
+        }
+        info!("Connection limiter rebuilt: {}", changes.join(", "));
+        *limiter = ConnectionLimiter::new(limits.max_attempts, window);
+    }
+
+    /// Put a reloaded gate list into effect. Gates whose ssh settings are
+    /// unchanged keep what was prepared for them at startup; new and changed
+    /// gates are prepared the same way. Returns the labels of the gates that
+    /// changed or were removed, whose tunnels have to reconnect.
+    async fn reload_gates(&self, gates: &[SshConfig]) -> Result<Vec<String>> {
+        let current = self.gate_selector.lock().unwrap().gates.clone();
+        if current.len() == gates.len()
+            && current
+                .iter()
+                .zip(gates)
+                .all(|(c, g)| same_gate(c, g) && c.weight == g.weight)
+        {
+            return Ok(Vec::new());
+        }
+
+        for gate in gates {
+            match current.iter().find(|c| gate_id(c) == gate_id(gate)) {
+                Some(old) if same_gate(old, gate) => {
+                    if old.weight != gate.weight {
+                        info!(
+                            "Gate {} weight changed: {} -> {}",
+                            gate_id(gate),
+                            old.weight,
+                            gate.weight
+                        );
+                    }
+                }
+                Some(old) => info!(
+                    "Gate {} changed: {}",
+                    gate_id(gate),
+                    gate_changes(old, gate).join(", ")
+                ),
+                None => info!("Gate {} added", gate_id(gate)),
+            }
+        }
+        for old in &current {
+            if !gates.iter().any(|g| gate_id(g) == gate_id(old)) {
+                info!("Gate {} removed", gate_id(old));
+            }
+        }
+
+        let fresh: Vec<SshConfig> = gates
+            .iter()
+            .filter(|g| !current.iter().any(|c| same_gate(c, g)))
+            .cloned()
+            .collect();
+        let mut prepared = Self::prepare_gates(&fresh, &self.metrics)
+            .await?
+            .into_iter();
+        let next = gates
+            .iter()
+            .map(|gate| match current.iter().find(|c| same_gate(c, gate)) {
+                Some(kept) => SshConfig {
+                    weight: gate.weight,
+                    ..kept.clone()
+                },
+                None => prepared.next().expect("every new gate is prepared"),
+            })
+            .collect();
+
+        let mut selector = self.gate_selector.lock().unwrap();
+        let mut retired = std::mem::replace(&mut *selector, GateSelector::new(next));
+        // Gates carried over keep their pinned known_hosts file; the rest are
+        // removed when the old selector is dropped
+        retired
+            .gates
+            .retain(|old| !selector.gates.iter().any(|g| same_gate(old, g)));
+        Ok(retired.gates.iter().map(SshConfig::label).collect())
+    }
+
+    /// Stop tunnel tasks, giving them `STOP_GRACE` to close their ssh process
+    async fn stop_tunnels(stopping: Vec<RunningTunnel>) {
+        for tunnel in &stopping {
+            tunnel.control.stop();
+        }
+
+        let deadline = time::Instant::now() + STOP_GRACE;
+        for tunnel in stopping {
+            let mut handle = tunnel.handle;
+            if time::timeout_at(deadline, &mut handle).await.is_err() {
+                // Waiting out a backoff; there is no ssh process to close
+                handle.abort();
+            }
+        }
+    }
+
+    fn wake_all(&self) {
+        self.controls
+            .read()
+            .unwrap()
+            .values()
+            .for_each(|c| c.wake());
     }
 
     pub async fn shutdown(&self) -> Result<()> {
         info!("Initiating graceful shutdown...");
         self.shutdown.store(true, Ordering::Relaxed);
+        self.wake_all();
 
         // Give tunnels time to clean up
         time::sleep(Duration::from_secs(2)).await;
@@ -218,10 +1199,13 @@ impl TunnelManager {
     async fn monitor_tunnel_status(
         metrics: Arc<MetricsCollector>,
         config: Config,
+        tunnels: Arc<RwLock<Vec<TunnelConfig>>>,
         shutdown: Arc<AtomicBool>,
     ) {
         info!("Starting tunnel status monitoring");
 
+        let started = Instant::now();
+        let startup_grace = Duration::from_secs(config.limits.startup_grace_secs);
         let mut interval = tokio::time::interval(Duration::from_secs(60)); // Check every minute
         interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
 
@@ -232,7 +1216,8 @@ impl TunnelManager {
             let mut active_count = 0;
             let mut total_count = 0;
 
-            for tunnel_config in &config.tunnels {
+            let tunnels = tunnels.read().unwrap().clone();
+            for tunnel_config in &tunnels {
                 total_count += 1;
 
                 // Check if tunnel is actively running
@@ -243,22 +1228,64 @@ impl TunnelManager {
                     .map(|s| s.status == TunnelStatus::Connected)
                     .unwrap_or(false);
                 let attempts = stats.as_ref().map(|s| s.reconnect_count).unwrap_or(0);
+                let gate = stats
+                    .and_then(|s| s.gate.clone())
+                    .or_else(|| config.gates().first().map(|g| g.label()))
+                    .unwrap_or_default();
+                // Dynamic tunnels have no fixed remote port
+                let target = if tunnel_config.direction == "dynamic" {
+                    format!("{} (SOCKS)", gate)
+                } else {
+                    format!("{}:{}", gate, tunnel_config.remote_port)
+                };
 
-                if is_connected {
-                    active_count += 1;
-                    status_report.push_str(&format!(
-                        "  ✓ {} → {}:{} (Active)\n",
-                        tunnel_config.name, config.gate.host, tunnel_config.remote_port
-                    ));
+                let is_paused = stats
+                    .as_ref()
+                    .map(|s| s.status == TunnelStatus::Paused)
+                    .unwrap_or(false);
+                let is_waiting_local = stats
+                    .as_ref()
+                    .map(|s| s.status == TunnelStatus::WaitingLocal)
+                    .unwrap_or(false);
+
+                if is_connected {
+                    active_count += 1;
+                    status_report.push_str(&format!(
+                        "  ✓ {} → {} (Active)\n",
+                        tunnel_config.log_name(),
+                        target
+                    ));
+                } else if is_paused {
+                    status_report.push_str(&format!(
+                        "  ‖ {} → {} (Paused)\n",
+                        tunnel_config.log_name(),
+                        target
+                    ));
+                } else if is_waiting_local {
+                    status_report.push_str(&format!(
+                        "  … {} → {} (Waiting for local service)\n",
+                        tunnel_config.log_name(),
+                        target
+                    ));
                 } else if attempts > 0 {
                     status_report.push_str(&format!(
-                        "  ⚠ {} → {}:{} (Reconnecting, {} attempts)\n",
-                        tunnel_config.name, config.gate.host, tunnel_config.remote_port, attempts
+                        "  ⚠ {} → {} (Reconnecting, {} attempts)\n",
+                        tunnel_config.log_name(),
+                        target,
+                        attempts
+                    ));
+                } else if tunnel_config.enabled && started.elapsed() < startup_grace {
+                    // Not failed yet and still within the boot window
+                    status_report.push_str(&format!(
+                        "  … {} → {} (Starting)\n",
+                        tunnel_config.log_name(),
+                        target
                     ));
                 } else {
                     status_report.push_str(&format!(
-                        "  ✗ {} → {}:{} (Inactive)\n",
-                        tunnel_config.name, config.gate.host, tunnel_config.remote_port
+                        "  ✗ {} → {} (Inactive)\n",
+                        tunnel_config.log_name(),
+                        target
                     ));
                 }
             }
@@ -274,55 +1301,130 @@ impl TunnelManager {
 
     async fn manage_ssh_cli_tunnel(
         tunnel: Tunnel,
-        ssh_config: crate::config::SshConfig,
-        metrics: Arc<MetricsCollector>,
-        connection_limiter: Arc<Mutex<ConnectionLimiter>>,
-        shutdown: Arc<AtomicBool>,
-    ) {
+        context: TunnelContext,
+        control: Arc<TunnelControl>,
+    ) -> Result<()> {
+        let TunnelContext {
+            limits,
+            gate_selector,
+            metrics,
+            connection_limiter,
+            audit,
+            shutdown,
+        } = context;
+        let _task = metrics.track_task();
+        let max_backoff = Duration::from_secs(limits.max_backoff_secs.max(1));
         let mut delay = Duration::from_secs(1);
         let mut tunnel_metrics = TunnelMetrics {
             reconnect_count: 0,
             last_error: None,
         };
 
-        let server_display = get_server_display_name(&ssh_config.host, &ssh_config.server_name);
+        let (mut gate_index, mut ssh_config) = gate_selector.lock().unwrap().pick(&tunnel, None);
+        let mut failed_gate = None;
+        let mut waiting_local = false;
+        let mut paused = false;
+        let mut recent_reconnects = VecDeque::new();
+        let mut server_display = get_server_display_name(&ssh_config.host, &ssh_config.server_name);
 
         info!(
             "Tunnel '{}' -> {} (Direction: {}) - Initializing connection",
-            tunnel.id,
+            tunnel.log_name,
             server_display,
-            if tunnel.direction == TunnelDirection::Send {
-                "LocalPush"
-            } else {
-                "RemotePull"
+            match tunnel.direction {
+                TunnelDirection::Send => "LocalPush",
+                TunnelDirection::Receive => "RemotePull",
+                TunnelDirection::Dynamic => "DynamicProxy",
             }
         );
 
         metrics.update_tunnel_status(&tunnel.id, TunnelStatus::Connecting);
 
         loop {
-            if shutdown.load(Ordering::Relaxed) {
+            if shutdown.load(Ordering::Relaxed) || control.is_stopped() {
                 info!(
                     "Tunnel '{}' -> {} - Shutting down",
-                    tunnel.id, server_display
+                    tunnel.log_name, server_display
                 );
                 metrics.update_tunnel_status(&tunnel.id, TunnelStatus::Disconnected);
                 break;
             }
 
+            if control.is_paused() {
+                if !paused {
+                    info!(
+                        "Tunnel '{}' -> {} - Paused",
+                        tunnel.log_name, server_display
+                    );
+                    paused = true;
+                }
+                metrics.update_tunnel_status(&tunnel.id, TunnelStatus::Paused);
+                time::sleep(PAUSE_POLL_INTERVAL).await;
+                continue;
+            }
+            if paused {
+                info!(
+                    "Tunnel '{}' -> {} - Resumed",
+                    tunnel.log_name, server_display
+                );
+                paused = false;
+                delay = Duration::from_secs(1);
+            }
+
+            // Don't publish a forward on the gate that can only fail
+            if tunnel.direction == TunnelDirection::Send
+                && tunnel.require_local_service
+                && !Self::local_service_up(&tunnel).await
+            {
+                if !waiting_local {
+                    info!(
+                        "Tunnel '{}' -> {} - Waiting for local service {}",
+                        tunnel.log_name,
+                        server_display,
+                        tunnel.local_endpoint()
+                    );
+                    waiting_local = true;
+                }
+                metrics.update_tunnel_status(&tunnel.id, TunnelStatus::WaitingLocal);
+                time::sleep(LOCAL_SERVICE_POLL_INTERVAL).await;
+                continue;
+            }
+            if waiting_local {
+                info!(
+                    "Tunnel '{}' -> {} - Local service {} is up",
+                    tunnel.log_name,
+                    server_display,
+                    tunnel.local_endpoint()
+                );
+                waiting_local = false;
+            }
+
+            // Every attempt after the first picks a gate, moving off one that just failed
+            if tunnel_metrics.reconnect_count > 0 {
+                (gate_index, ssh_config) = gate_selector.lock().unwrap().pick(&tunnel, failed_gate);
+                server_display = get_server_display_name(&ssh_config.host, &ssh_config.server_name);
+            }
+
             // Check connection rate limiting
-            let can_attempt = {
+            let wait = {
                 let mut limiter = connection_limiter.lock().unwrap();
-                limiter.can_attempt(&ssh_config.host)
+                if limiter.can_attempt(&ssh_config.host) {
+                    None
+                } else {
+                    Some(limiter.time_until_available(&ssh_config.host))
+                }
             };
 
-            if !can_attempt {
+            if let Some(wait) = wait {
+                let wait = wait + jitter(Duration::from_secs(1));
                 warn!(
-                    "Tunnel '{}' -> {} - Rate limit exceeded, waiting to retry...",
-                    tunnel.id, server_display
+                    "Tunnel '{}' -> {} - Rate limit exceeded, retrying in {:.1}s",
+                    tunnel.log_name,
+                    server_display,
+                    wait.as_secs_f64()
                 );
                 metrics.update_tunnel_status(&tunnel.id, TunnelStatus::Error);
-                time::sleep(Duration::from_secs(60)).await;
+                time::sleep(wait).await;
                 continue;
             }
 
@@ -331,20 +1433,46 @@ impl TunnelManager {
             if tunnel_metrics.reconnect_count == 1 {
                 info!(
                     "Tunnel '{}' -> {} - Establishing connection...",
-                    tunnel.id, server_display
+                    tunnel.log_name, server_display
                 );
             } else {
                 warn!(
                     "Tunnel '{}' -> {} - Reconnecting (attempt #{}) after disconnection",
-                    tunnel.id, server_display, tunnel_metrics.reconnect_count
+                    tunnel.log_name, server_display, tunnel_metrics.reconnect_count
                 );
+                metrics.increment_reconnect(&tunnel.id);
+
+                // A tunnel that keeps flapping stays flagged even once it settles
+                if let Some(threshold) = tunnel.degraded_reconnects {
+                    recent_reconnects.push_back(Instant::now());
+                    while recent_reconnects
+                        .front()
+                        .is_some_and(|t| t.elapsed() > tunnel.degraded_window)
+                    {
+                        recent_reconnects.pop_front();
+                    }
+                    if recent_reconnects.len() > threshold as usize
+                        && metrics.mark_degraded(&tunnel.id, tunnel.degraded_fails_health)
+                    {
+                        warn!(
+                            "Tunnel '{}' -> {} - Degraded: {} reconnects within {}s",
+                            tunnel.log_name,
+                            server_display,
+                            recent_reconnects.len(),
+                            tunnel.degraded_window.as_secs()
+                        );
+                    }
+                }
             }
 
             metrics.update_tunnel_status(&tunnel.id, TunnelStatus::Connecting);
 
-            // Log direction-specific details
-            let server_display =
-                get_server_display_name(&tunnel.remote_host, &ssh_config.server_name);
+            // Log direction-specific details; dynamic tunnels have no remote target
+            let target_host = match tunnel.direction {
+                TunnelDirection::Dynamic => &ssh_config.host,
+                _ => &tunnel.remote_host,
+            };
+            let server_display = get_server_display_name(target_host, &ssh_config.server_name);
             match tunnel.direction {
                 TunnelDirection::Receive => {
                     // Remote pull: SSH -L (pull remote service to local)
@@ -360,51 +1488,308 @@ impl TunnelManager {
                         tunnel.local_host, tunnel.local_port, server_display, tunnel.remote_port
                     );
                 }
+                TunnelDirection::Dynamic => {
+                    // SOCKS proxy: SSH -D (gate opens connections on the client's behalf)
+                    info!(
+                        "Dynamic Proxy: SOCKS on {}:{} via {}",
+                        tunnel.local_host, tunnel.local_port, server_display
+                    );
+                }
             }
 
-            match Self::run_ssh_cli_tunnel(&tunnel, &ssh_config, &metrics, &shutdown).await {
+            metrics.record_gate_selection(&tunnel.id, &ssh_config.label());
+
+            let span = tracing::debug_span!(
+                "connection_attempt",
+                tunnel = %tunnel.id,
+                gate = %ssh_config.label(),
+                attempt = tunnel_metrics.reconnect_count,
+                failure_kind = tracing::field::Empty,
+            );
+
+            match Self::run_ssh_cli_tunnel(
+                &tunnel,
+                &ssh_config,
+                &metrics,
+                &audit,
+                &control,
+                &shutdown,
+            )
+            .instrument(span.clone())
+            .await
+            {
                 Ok(_) => {
+                    failed_gate = None;
                     tunnel_metrics.last_error = None;
                     delay = Duration::from_secs(1);
+                    metrics.update_tunnel_status(&tunnel.id, TunnelStatus::Disconnected);
+                    if control.is_paused() {
+                        continue;
+                    }
                     warn!(
                         "Tunnel '{}' -> {} - Connection terminated normally, preparing to reconnect...",
-                        tunnel.id, server_display
+                        tunnel.log_name, server_display
                     );
-                    metrics.update_tunnel_status(&tunnel.id, TunnelStatus::Disconnected);
                 }
                 Err(e) => {
+                    failed_gate = Some(gate_index);
+                    span.record(
+                        "failure_kind",
+                        e.downcast_ref::<TunnelFailure>()
+                            .map(|f| f.kind())
+                            .unwrap_or("connection_lost"),
+                    );
                     tunnel_metrics.last_error = Some(e.to_string());
+                    metrics.record_error(&tunnel.id, &e.to_string());
                     error!(
                         "Tunnel '{}' -> {} - Connection failed: {}",
-                        tunnel.id, server_display, e
+                        tunnel.log_name, server_display, e
                     );
-                    metrics.update_tunnel_status(&tunnel.id, TunnelStatus::Error);
+                    match e.downcast_ref::<TunnelFailure>() {
+                        Some(TunnelFailure::ForwardClosed) => {
+                            metrics.increment_forward_closed(&tunnel.id);
+                            metrics.update_tunnel_status(&tunnel.id, TunnelStatus::ForwardClosed);
+                        }
+                        Some(TunnelFailure::AuthFailure) if limits.exit_on_auth_failure => {
+                            error!(
+                                "Tunnel '{}' -> {} - Authentication rejected, giving up",
+                                tunnel.log_name, server_display
+                            );
+                            metrics.update_tunnel_status(&tunnel.id, TunnelStatus::Error);
+                            return Err(e);
+                        }
+                        Some(TunnelFailure::Throttled(hint)) => {
+                            // max_backoff_secs is a hard cap, even on the gate's hint
+                            delay = delay.max(hint.backoff).min(max_backoff);
+                            metrics.update_tunnel_status(&tunnel.id, TunnelStatus::Error);
+                        }
+                        _ => metrics.update_tunnel_status(&tunnel.id, TunnelStatus::Error),
+                    }
 
                     // Show retry information
                     info!(
                         "Tunnel '{}' -> {} - Will retry in {} seconds...",
-                        tunnel.id,
+                        tunnel.log_name,
                         server_display,
                         delay.as_secs()
                     );
                 }
             }
 
-            if !shutdown.load(Ordering::Relaxed) {
-                warn!("Reconnecting tunnel {} in {}s", tunnel.id, delay.as_secs());
+            if !shutdown.load(Ordering::Relaxed) && !control.is_stopped() {
+                warn!(
+                    "Reconnecting tunnel {} in {}s",
+                    tunnel.log_name,
+                    delay.as_secs()
+                );
                 metrics.update_tunnel_status(&tunnel.id, TunnelStatus::Connecting);
                 time::sleep(delay).await;
-                delay = std::cmp::min(delay * 2, Duration::from_secs(60));
+                delay = std::cmp::min(delay * 2, max_backoff);
             }
         }
 
         metrics.update_tunnel_status(&tunnel.id, TunnelStatus::Disconnected);
+        Ok(())
+    }
+
+    /// Authentication and connection options for a gate
+    fn gate_options(tunnel_id: &str, ssh_config: &SshConfig) -> Vec<String> {
+        if !ssh_config.use_ssh_config {
+            return Self::default_ssh_options(tunnel_id, ssh_config);
+        }
+
+        // The alias's ~/.ssh/config entry supplies everything else; user,
+        // key and known_hosts are only passed when set explicitly in our config
+        let mut options = Vec::new();
+        if !ssh_config.user.is_empty() {
+            options.push("-l".to_string());
+            options.push(ssh_config.user.clone());
+        }
+        if !ssh_config.key_path.as_os_str().is_empty() {
+            options.push("-i".to_string());
+            options.push(ssh_config.key_path.to_string_lossy().to_string());
+        }
+        options.extend(Self::certificate_options(ssh_config));
+        if ssh_config.known_hosts_path.is_some()
+            || ssh_config.host_key_policy.is_some()
+            || ssh_config.pinned_known_hosts.is_some()
+        {
+            options.extend(Self::host_key_options(ssh_config));
+        }
+        if ssh_config.ssh_verbose {
+            options.push("-v".to_string());
+        }
+        options
+    }
+
+    /// The gate to connect to, last on the command line
+    fn destination_args(ssh_config: &SshConfig) -> Vec<String> {
+        let mut args = Vec::new();
+
+        // Connect to the pinned address but verify the host key under the gate's name
+        if let Some(ip) = ssh_config.pinned_ip {
+            args.push("-o".to_string());
+            args.push(format!("HostName={}", ip));
+            args.push("-o".to_string());
+            args.push(format!("HostKeyAlias={}", ssh_config.host));
+        }
+
+        // The hop to the bastion is a separate ssh connection configured by
+        // ~/.ssh/config and the agent, not by the gate's options
+        if let Some(jump) = &ssh_config.jump_host {
+            args.push("-J".to_string());
+            args.push(jump.clone());
+        }
+
+        if ssh_config.use_ssh_config {
+            args.push(ssh_config.host.clone());
+        } else {
+            args.push(format!("{}@{}", ssh_config.user, ssh_config.host));
+        }
+        args
+    }
+
+    /// Run a trivial command on the gate at startup. Accounts with a
+    /// ForceCommand print something else (or nothing) and tend to break `-N`
+    /// forwarding in confusing ways, so this only warns.
+    async fn preflight(gate: &SshConfig) {
+        let token = format!("m-tunnel-preflight-{}", std::process::id());
+
+        let mut args = Self::gate_options("preflight", gate);
+        // BatchMode would also stop ssh from asking askpass for the key passphrase
+        if gate.key_passphrase().is_none() {
+            args.extend(["-o".to_string(), "BatchMode=yes".to_string()]);
+        }
+        args.extend(["-o".to_string(), format!("ConnectTimeout={}", gate.timeout)]);
+        args.extend(Self::destination_args(gate));
+        args.push(format!("echo {}", token));
+
+        let output = Command::new("ssh")
+            .args(&args)
+            .envs(askpass_env(gate))
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output();
+        let output = match time::timeout(Duration::from_secs(gate.timeout.max(1) * 2), output).await
+        {
+            Ok(Ok(output)) => output,
+            Ok(Err(e)) => {
+                warn!(
+                    "Preflight for gate {} could not run ssh: {}",
+                    gate.label(),
+                    e
+                );
+                return;
+            }
+            Err(_) => {
+                warn!("Preflight for gate {} timed out", gate.label());
+                return;
+            }
+        };
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if stdout.lines().any(|l| l.trim() == token) {
+            info!("Preflight for gate {} passed", gate.label());
+        } else if output.status.code() == Some(255) {
+            warn!(
+                "Preflight for gate {} could not connect: {}",
+                gate.label(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        } else {
+            warn!(
+                "Gate {} did not run the preflight command ({}, output {:?}). The account \
+                 probably has a ForceCommand or similar restriction, and forwarding may not work",
+                gate.label(),
+                output.status,
+                stdout.trim()
+            );
+        }
+    }
+
+    /// Options m-tunnel sets itself when not deferring to ~/.ssh/config
+    fn default_ssh_options(tunnel_id: &str, ssh_config: &SshConfig) -> Vec<String> {
+        // ssh only prints the login banner at INFO and above, and the server
+        // version at DEBUG1
+        let log_level = if ssh_config.ssh_verbose {
+            "LogLevel=DEBUG1"
+        } else if ssh_config.banner_pattern.is_some() {
+            "LogLevel=INFO"
+        } else {
+            "LogLevel=ERROR"
+        };
+
+        let mut options = Self::host_key_options(ssh_config);
+        options.extend([
+            "-o".to_string(),
+            log_level.to_string(),
+            "-o".to_string(),
+            format!(
+                "ServerAliveInterval={}",
+                keepalive_interval(tunnel_id, ssh_config.keepalive_interval)
+            ),
+            "-o".to_string(),
+            "ServerAliveCountMax=3".to_string(),
+            "-p".to_string(),
+            ssh_config.port.to_string(),
+        ]);
+        // Agent gates have no key_path of their own; a tunnel's key_path still applies
+        if !ssh_config.key_path.as_os_str().is_empty() {
+            options.push("-i".to_string());
+            options.push(ssh_config.key_path.to_string_lossy().to_string());
+        }
+        options.extend(Self::certificate_options(ssh_config));
+        options
+    }
+
+    fn certificate_options(ssh_config: &SshConfig) -> Vec<String> {
+        match &ssh_config.cert_path {
+            Some(path) => vec![
+                "-o".to_string(),
+                format!("CertificateFile={}", path.display()),
+            ],
+            None => Vec::new(),
+        }
+    }
+
+    /// Host key checking according to the gate's host_key_policy, against its
+    /// pinned or configured known_hosts file, or ssh's default otherwise
+    fn host_key_options(ssh_config: &SshConfig) -> Vec<String> {
+        let checking = match ssh_config.host_key_checking() {
+            HostKeyPolicy::AcceptNew => "StrictHostKeyChecking=accept-new",
+            HostKeyPolicy::Strict => "StrictHostKeyChecking=yes",
+            HostKeyPolicy::Off => {
+                return vec![
+                    "-o".to_string(),
+                    "StrictHostKeyChecking=no".to_string(),
+                    "-o".to_string(),
+                    "UserKnownHostsFile=/dev/null".to_string(),
+                ];
+            }
+        };
+
+        let mut options = vec!["-o".to_string(), checking.to_string()];
+        let known_hosts = ssh_config
+            .pinned_known_hosts
+            .as_ref()
+            .or(ssh_config.known_hosts_path.as_ref());
+        if let Some(path) = known_hosts {
+            options.extend([
+                "-o".to_string(),
+                format!("UserKnownHostsFile={}", path.display()),
+                "-o".to_string(),
+                "HashKnownHosts=yes".to_string(),
+            ]);
+        }
+        options
     }
 
     async fn run_ssh_cli_tunnel(
         tunnel: &Tunnel,
-        ssh_config: &crate::config::SshConfig,
+        ssh_config: &SshConfig,
         metrics: &Arc<MetricsCollector>,
+        audit: &AuditLog,
+        control: &TunnelControl,
         shutdown: &Arc<AtomicBool>,
     ) -> Result<()> {
         let server_display = get_server_display_name(&ssh_config.host, &ssh_config.server_name);
@@ -414,88 +1799,377 @@ impl TunnelManager {
         // Log successful connection establishment
         info!(
             "Tunnel '{}' -> {} - Connection established successfully ✓",
-            tunnel.id, server_display
+            tunnel.log_name, server_display
         );
 
         let mut ssh_args = vec![
             "-N".to_string(), // Don't execute remote command
             "-o".to_string(),
-            "StrictHostKeyChecking=no".to_string(),
-            "-o".to_string(),
-            "UserKnownHostsFile=/dev/null".to_string(),
-            "-o".to_string(),
-            "LogLevel=ERROR".to_string(),
-            "-o".to_string(),
-            "ServerAliveInterval=30".to_string(), // Keep alive
-            "-o".to_string(),
-            "ServerAliveCountMax=3".to_string(),
-            "-p".to_string(),
-            ssh_config.port.to_string(),
-            "-i".to_string(),
-            ssh_config.key_path.to_string_lossy().to_string(),
+            "ExitOnForwardFailure=yes".to_string(), // Fail fast if the gate refuses the forward
         ];
 
+        ssh_args.extend(Self::gate_options(&tunnel.id, ssh_config));
+
+        // IPQoS takes the whole ToS byte, DSCP is its upper six bits
+        if let Some(dscp) = tunnel.dscp {
+            ssh_args.push("-o".to_string());
+            ssh_args.push(format!("IPQoS={}", u32::from(dscp) << 2));
+        }
+
         // Add tunnel-specific arguments
         match tunnel.direction {
             TunnelDirection::Receive => {
                 // Remote pull: SSH -L (pull remote service to local)
                 ssh_args.push("-L".to_string());
-                ssh_args.push(format!(
-                    "{}:{}:{}",
-                    tunnel.local_port, tunnel.remote_host, tunnel.remote_port
-                ));
+                match tunnel.local_socket_path() {
+                    Some(path) => {
+                        // Replace a socket left behind by a previous run
+                        Self::remove_stale_socket(path);
+                        ssh_args.push(format!(
+                            "{}:{}:{}",
+                            path,
+                            bracket_ipv6(&tunnel.remote_host),
+                            tunnel.remote_port
+                        ));
+                        ssh_args.push("-o".to_string());
+                        ssh_args.push("StreamLocalBindUnlink=yes".to_string());
+                    }
+                    None => ssh_args.push(format!(
+                        "{}:{}:{}",
+                        tunnel.local_endpoint(),
+                        bracket_ipv6(&tunnel.remote_host),
+                        tunnel.remote_port
+                    )),
+                }
             }
             TunnelDirection::Send => {
                 // Local push: SSH -R (push local service to remote server)
                 ssh_args.push("-R".to_string());
                 ssh_args.push(format!(
-                    "{}:{}:{}",
-                    tunnel.remote_port, tunnel.local_host, tunnel.local_port
+                    "{}:{}",
+                    tunnel.remote_port,
+                    tunnel.local_endpoint()
                 ));
             }
+            TunnelDirection::Dynamic => {
+                // SOCKS proxy: SSH -D (listen locally, connect out from the gate)
+                ssh_args.push("-D".to_string());
+                ssh_args.push(tunnel.local_endpoint());
+            }
         }
 
-        ssh_args.push(format!("{}@{}", ssh_config.user, ssh_config.host));
+        ssh_args.extend(Self::destination_args(ssh_config));
+
+        // A fresh process already satisfies any reconnect asked for in the meantime
+        control.take_reconnect_request();
 
         let mut ssh_process = Command::new("ssh")
             .args(&ssh_args)
+            .envs(&tunnel.env)
+            .envs(askpass_env(ssh_config))
             .stdin(Stdio::null())
             .stdout(Stdio::null())
             .stderr(Stdio::piped())
+            .kill_on_drop(true)
             .spawn()
             .context("Failed to start tunnel process")?;
+        let _process = metrics.track_ssh_process();
+        let spawned = Instant::now();
+
+        audit.record(AuditEvent::Connect, &tunnel.id, &ssh_config.host, None);
+        tracing::debug!("ssh process started");
+
+        // Forward ssh diagnostics to the log so forward rejections are visible
+        let (stderr_tx, mut stderr_rx) = mpsc::unbounded_channel::<String>();
+        if let Some(stderr) = ssh_process.stderr.take() {
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(stderr).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if stderr_tx.send(line).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        let mut next_probe = Instant::now() + HEALTH_PROBE_INTERVAL;
+        let banner_deadline = Instant::now() + BANNER_WINDOW;
+        // Only gates with a banner_pattern run ssh at a level that prints the banner
+        let mut banner = ssh_config.banner_pattern.as_ref().map(|_| Vec::new());
+        let mut failed_probes = 0;
+        let mut auth_rejected = false;
+        let mut throttled = None;
+        let mut forward_ready = false;
+        let mut pending_line = None;
+        let verification = Self::verify_forward(tunnel);
+        tokio::pin!(verification);
+        let mut verify_pending = tunnel.verify.is_some();
+        let mut verify_result: Option<Result<()>> = None;
+        // Send tunnels listen on the gate, so only local listeners can be timed
+        let listening = Self::wait_listening(tunnel);
+        tokio::pin!(listening);
+        let mut listen_pending = tunnel.direction != TunnelDirection::Send;
 
         // Wait for shutdown or process exit
         loop {
-            if shutdown.load(Ordering::Relaxed) {
+            let stopped = control.is_stopped();
+            if stopped || shutdown.load(Ordering::Relaxed) {
+                let (message, reason) = if stopped {
+                    ("Removed or changed by reload", "reload")
+                } else {
+                    ("Shutdown signal received", "shutdown")
+                };
+                info!(
+                    "Tunnel '{}' -> {} - {}, terminating process",
+                    tunnel.log_name, server_display, message
+                );
+                let _ = ssh_process.kill().await;
+                audit.record(
+                    AuditEvent::Disconnect,
+                    &tunnel.id,
+                    &ssh_config.host,
+                    Some(reason),
+                );
+                tracing::debug!(reason, "ssh process stopped");
+                if tunnel.direction == TunnelDirection::Receive {
+                    if let Some(path) = tunnel.local_socket_path() {
+                        Self::remove_stale_socket(path);
+                    }
+                }
+                break;
+            }
+
+            if control.take_reconnect_request() {
+                info!(
+                    "Tunnel '{}' -> {} - Reconnect requested, terminating process",
+                    tunnel.log_name, server_display
+                );
+                let _ = ssh_process.kill().await;
+                audit.record(
+                    AuditEvent::Disconnect,
+                    &tunnel.id,
+                    &ssh_config.host,
+                    Some("reconnect requested"),
+                );
+                tracing::debug!(reason = "reconnect requested", "ssh process stopped");
+                break;
+            }
+
+            if control.is_paused() {
                 info!(
-                    "Tunnel '{}' -> {} - Shutdown signal received, terminating process",
-                    tunnel.id, server_display
+                    "Tunnel '{}' -> {} - Pause requested, terminating process",
+                    tunnel.log_name, server_display
                 );
                 let _ = ssh_process.kill().await;
+                audit.record(
+                    AuditEvent::Disconnect,
+                    &tunnel.id,
+                    &ssh_config.host,
+                    Some("paused"),
+                );
+                tracing::debug!(reason = "paused", "ssh process stopped");
                 break;
             }
 
-            // Check if process is still running
-            match ssh_process.try_wait() {
-                Ok(Some(status)) => {
+            if control.take_injected_failure() {
+                warn!(
+                    "Tunnel '{}' -> {} - Injecting failure, killing process",
+                    tunnel.log_name, server_display
+                );
+                let _ = ssh_process.kill().await;
+                audit.record(
+                    AuditEvent::Disconnect,
+                    &tunnel.id,
+                    &ssh_config.host,
+                    Some("injected failure"),
+                );
+                tracing::debug!(reason = "injected failure", "ssh process stopped");
+                return Err(anyhow::anyhow!("Injected failure"));
+            }
+
+            if let Some(result) = verify_result.take() {
+                metrics.record_verification(&tunnel.id, result.is_ok());
+                match result {
+                    Ok(()) => info!(
+                        "Tunnel '{}' -> {} - Forwarded service verified",
+                        tunnel.log_name, server_display
+                    ),
+                    Err(e) => {
+                        error!(
+                            "Tunnel '{}' -> {} - Verification failed: {:#}, forcing reconnect",
+                            tunnel.log_name, server_display, e
+                        );
+                        let _ = ssh_process.kill().await;
+                        audit.record(
+                            AuditEvent::Disconnect,
+                            &tunnel.id,
+                            &ssh_config.host,
+                            Some("verification failed"),
+                        );
+                        tracing::debug!(reason = "verification failed", "ssh process stopped");
+                        return Err(TunnelFailure::VerificationFailed.into());
+                    }
+                }
+            }
+
+            let received = pending_line
+                .take()
+                .into_iter()
+                .chain(std::iter::from_fn(|| stderr_rx.try_recv().ok()));
+            for line in received {
+                // ssh prints the banner verbatim, so early lines that aren't
+                // ssh's own messages are taken to be banner. They are still
+                // inspected, a disconnect message looks no different.
+                let in_banner = match banner.as_mut() {
+                    Some(banner) if !is_ssh_diagnostic(&line) => {
+                        banner.push(line.clone());
+                        true
+                    }
+                    _ => false,
+                };
+                match Self::inspect_ssh_output(tunnel, ssh_config, metrics, audit, &line, in_banner)
+                {
+                    Some(TunnelFailure::ForwardClosed) => failed_probes += 1,
+                    Some(TunnelFailure::AuthFailure) => auth_rejected = true,
+                    Some(failure @ TunnelFailure::Throttled(_)) => throttled = Some(failure),
+                    Some(TunnelFailure::VerificationFailed) | None => {}
+                }
+            }
+
+            if Instant::now() >= banner_deadline {
+                if let Some(lines) = banner.take() {
+                    let text = Self::log_banner(tunnel, &server_display, lines);
+                    if !Self::banner_matches(ssh_config, &text) {
+                        error!(
+                            "Tunnel '{}' -> {} - Gate banner does not match banner_pattern, disconnecting",
+                            tunnel.log_name, server_display
+                        );
+                        let _ = ssh_process.kill().await;
+                        audit.record(
+                            AuditEvent::Disconnect,
+                            &tunnel.id,
+                            &ssh_config.host,
+                            Some("banner mismatch"),
+                        );
+                        return Err(anyhow::anyhow!("Gate banner does not match banner_pattern"));
+                    }
+                }
+            }
+
+            // The process is alive, so a dead forward means the gate closed it
+            if Instant::now() >= next_probe {
+                next_probe = Instant::now() + HEALTH_PROBE_INTERVAL;
+                if Self::probe_forward(tunnel).await {
+                    if !forward_ready {
+                        forward_ready = true;
+                        metrics.mark_ready(&tunnel.id);
+                        tracing::debug!("forward ready");
+                    }
+                    failed_probes = 0;
+                } else {
+                    failed_probes += 1;
+                    warn!(
+                        "Tunnel '{}' -> {} - Forward health probe failed ({}/{})",
+                        tunnel.log_name, server_display, failed_probes, FORWARD_CLOSED_THRESHOLD
+                    );
+                }
+            }
+
+            if failed_probes >= FORWARD_CLOSED_THRESHOLD {
+                error!(
+                    "Tunnel '{}' -> {} - Session alive but forward is closed, forcing reconnect",
+                    tunnel.log_name, server_display
+                );
+                let _ = ssh_process.kill().await;
+                audit.record(
+                    AuditEvent::Disconnect,
+                    &tunnel.id,
+                    &ssh_config.host,
+                    Some("forward closed"),
+                );
+                tracing::debug!(reason = "forward closed", "ssh process stopped");
+                return Err(TunnelFailure::ForwardClosed.into());
+            }
+
+            // Sleep until something happens: the process exits, ssh prints a
+            // line, a control switch flips, or the next probe or banner check
+            // is due. Idle tunnels cost nothing in between.
+            let deadline = match banner {
+                Some(_) => next_probe.min(banner_deadline),
+                None => next_probe,
+            };
+            let status = tokio::select! {
+                status = ssh_process.wait() => status,
+                Some(line) = stderr_rx.recv() => {
+                    pending_line = Some(line);
+                    continue;
+                }
+                _ = &mut listening, if listen_pending => {
+                    listen_pending = false;
+                    let latency = spawned.elapsed();
+                    metrics.set_latency(&tunnel.id, latency);
+                    tracing::debug!(latency_ms = latency.as_millis() as u64, "forward listening");
+                    if !forward_ready {
+                        forward_ready = true;
+                        metrics.mark_ready(&tunnel.id);
+                        tracing::debug!("forward ready");
+                    }
+                    continue;
+                }
+                result = &mut verification, if verify_pending => {
+                    verify_pending = false;
+                    verify_result = Some(result);
+                    continue;
+                }
+                _ = control.woken() => continue,
+                _ = time::sleep_until(deadline.into()) => continue,
+            };
+
+            match status {
+                Ok(status) => {
+                    // Collect the last diagnostics ssh printed before exiting
+                    while let Ok(Some(line)) =
+                        time::timeout(Duration::from_millis(200), stderr_rx.recv()).await
+                    {
+                        match Self::inspect_ssh_output(
+                            tunnel, ssh_config, metrics, audit, &line, false,
+                        ) {
+                            Some(TunnelFailure::AuthFailure) => auth_rejected = true,
+                            Some(failure @ TunnelFailure::Throttled(_)) => {
+                                throttled = Some(failure)
+                            }
+                            _ => {}
+                        }
+                    }
+                    if let Some(lines) = banner.take() {
+                        Self::log_banner(tunnel, &server_display, lines);
+                    }
                     warn!(
                         "Tunnel '{}' -> {} - Process terminated (status: {}), connection lost",
-                        tunnel.id, server_display, status
+                        tunnel.log_name, server_display, status
+                    );
+                    audit.record(
+                        AuditEvent::Disconnect,
+                        &tunnel.id,
+                        &ssh_config.host,
+                        Some(&status.to_string()),
                     );
+                    tracing::debug!(reason = %status, "ssh process stopped");
+                    if auth_rejected {
+                        return Err(TunnelFailure::AuthFailure.into());
+                    }
+                    if let Some(failure) = throttled {
+                        return Err(failure.into());
+                    }
                     return Err(anyhow::anyhow!(
                         "Connection process exited with status: {}",
                         status
                     ));
                 }
-                Ok(None) => {
-                    // Process still running, continue monitoring
-                    time::sleep(Duration::from_millis(500)).await;
-                }
                 Err(e) => {
                     error!(
                         "Tunnel '{}' -> {} - Failed to monitor process: {}",
-                        tunnel.id, server_display, e
+                        tunnel.log_name, server_display, e
                     );
                     return Err(anyhow::anyhow!(
                         "Failed to check tunnel process status: {}",
@@ -507,4 +2181,613 @@ impl TunnelManager {
 
         Ok(())
     }
+
+    /// Log a line of ssh stderr and record auth failures in the audit log.
+    /// Returns the failure the line reports, if any. Lines captured as the
+    /// login banner are logged with the banner unless they report a failure.
+    fn inspect_ssh_output(
+        tunnel: &Tunnel,
+        ssh_config: &SshConfig,
+        metrics: &MetricsCollector,
+        audit: &AuditLog,
+        line: &str,
+        in_banner: bool,
+    ) -> Option<TunnelFailure> {
+        let server_display = get_server_display_name(&ssh_config.host, &ssh_config.server_name);
+
+        // Verbose output from ssh_verbose gates
+        if line.starts_with("debug") {
+            debug!(
+                "Tunnel '{}' -> {} - {}",
+                tunnel.log_name, server_display, line
+            );
+            // debug1: Remote protocol version 2.0, remote software version OpenSSH_9.6
+            if let Some((protocol, software)) = line
+                .split_once("Remote protocol version ")
+                .and_then(|(_, rest)| rest.split_once(", remote software version "))
+            {
+                let version = format!("SSH-{}-{}", protocol, software.trim());
+                if metrics.record_gate_ssh_version(&ssh_config.label(), &version) {
+                    info!("Gate {} runs {}", server_display, version);
+                }
+            }
+            return None;
+        }
+
+        let failure = if is_auth_rejection(line) {
+            audit.record(
+                AuditEvent::AuthFailure,
+                &tunnel.id,
+                &ssh_config.host,
+                Some(line),
+            );
+            Some(TunnelFailure::AuthFailure)
+        } else if is_forward_rejection(line) {
+            Some(TunnelFailure::ForwardClosed)
+        } else {
+            THROTTLE_HINTS
+                .iter()
+                .find(|hint| hint.matches(line))
+                .map(TunnelFailure::Throttled)
+        };
+
+        if !in_banner || failure.is_some() {
+            warn!(
+                "Tunnel '{}' -> {} - {}",
+                tunnel.log_name, server_display, line
+            );
+        }
+        failure
+    }
+
+    /// Log the captured login banner and return it as one string
+    fn log_banner(tunnel: &Tunnel, server_display: &str, lines: Vec<String>) -> String {
+        let text = lines.join("\n");
+        if !text.is_empty() {
+            debug!(
+                "Tunnel '{}' -> {} - Gate banner:\n{}",
+                tunnel.log_name, server_display, text
+            );
+        }
+        text
+    }
+
+    /// Check the captured login banner against the gate's `banner_pattern`
+    fn banner_matches(ssh_config: &SshConfig, banner: &str) -> bool {
+        match &ssh_config.banner_pattern {
+            // Validated at config load
+            Some(pattern) => regex::Regex::new(pattern).is_ok_and(|re| re.is_match(banner)),
+            None => true,
+        }
+    }
+
+    /// Probe a running tunnel's local listener.
+    ///
+    /// A refused connection means ssh is no longer listening and counts as a
+    /// failure. An accepted one is healthy, even if it is closed straight away:
+    /// the service behind a working forward may simply be down. The connection
+    /// still makes ssh open a channel, so a gate that refuses the forward shows
+    /// up as an "administratively prohibited" line on stderr. Send tunnels
+    /// listen on the gate and cannot be probed from here; they rely on stderr
+    /// alone.
+    async fn probe_forward(tunnel: &Tunnel) -> bool {
+        if tunnel.direction == TunnelDirection::Send {
+            return true;
+        }
+
+        #[cfg(unix)]
+        if let Some(path) = tunnel.local_socket_path() {
+            return match time::timeout(
+                Duration::from_secs(5),
+                tokio::net::UnixStream::connect(path),
+            )
+            .await
+            {
+                Ok(result) => result.is_ok(),
+                Err(_) => false,
+            };
+        }
+
+        match time::timeout(
+            Duration::from_secs(5),
+            TcpStream::connect((tunnel.connect_host(), tunnel.local_port)),
+        )
+        .await
+        {
+            Ok(result) => result.is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    /// Wait until the tunnel's local listener accepts connections. ssh only
+    /// opens it once the gate has authenticated the session and the forward is
+    /// set up, so this marks the end of the connect.
+    async fn wait_listening(tunnel: &Tunnel) {
+        loop {
+            #[cfg(unix)]
+            if let Some(path) = tunnel.local_socket_path() {
+                if tokio::net::UnixStream::connect(path).await.is_ok() {
+                    return;
+                }
+                time::sleep(READY_POLL_INTERVAL).await;
+                continue;
+            }
+
+            if TcpStream::connect((tunnel.connect_host(), tunnel.local_port))
+                .await
+                .is_ok()
+            {
+                return;
+            }
+            time::sleep(READY_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Run the tunnel's verify block against its local listener. ssh may not
+    /// be listening yet, so refused connections are retried until the
+    /// verification timeout.
+    async fn verify_forward(tunnel: &Tunnel) -> Result<()> {
+        let Some(verify) = &tunnel.verify else {
+            return Ok(());
+        };
+        let timeout = Duration::from_secs(verify.timeout_secs.max(1));
+
+        let attempt = async {
+            loop {
+                #[cfg(unix)]
+                if let Some(path) = tunnel.local_socket_path() {
+                    if let Ok(stream) = tokio::net::UnixStream::connect(path).await {
+                        return Self::check_service(stream, verify, &tunnel.remote_host).await;
+                    }
+                    time::sleep(VERIFY_RETRY_INTERVAL).await;
+                    continue;
+                }
+
+                if let Ok(stream) =
+                    TcpStream::connect((tunnel.connect_host(), tunnel.local_port)).await
+                {
+                    return Self::check_service(stream, verify, &tunnel.remote_host).await;
+                }
+                time::sleep(VERIFY_RETRY_INTERVAL).await;
+            }
+        };
+
+        time::timeout(timeout, attempt)
+            .await
+            .map_err(|_| anyhow::anyhow!("no answer within {}s", timeout.as_secs()))?
+    }
+
+    /// Match the service's first line against `tcp_banner_regex`, or send a
+    /// GET for `http_path` and compare the response status
+    async fn check_service<S: AsyncRead + AsyncWrite + Unpin>(
+        mut stream: S,
+        verify: &VerifyConfig,
+        host: &str,
+    ) -> Result<()> {
+        if let Some(path) = &verify.http_path {
+            let request = format!(
+                "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+                path,
+                bracket_ipv6(host)
+            );
+            stream.write_all(request.as_bytes()).await?;
+        }
+
+        let mut line = Vec::new();
+        BufReader::new(stream)
+            .take(4096)
+            .read_until(b'\n', &mut line)
+            .await?;
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end();
+        if line.is_empty() {
+            return Err(anyhow::anyhow!("connection closed without a response"));
+        }
+
+        if let Some(pattern) = &verify.tcp_banner_regex {
+            // Validated at config load
+            let re = regex::Regex::new(pattern)?;
+            if !re.is_match(line) {
+                return Err(anyhow::anyhow!(
+                    "banner {:?} does not match tcp_banner_regex",
+                    line
+                ));
+            }
+            return Ok(());
+        }
+
+        // HTTP/1.1 200 OK
+        let status = line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or_else(|| anyhow::anyhow!("not an HTTP response: {:?}", line))?;
+        if status != verify.expected_status {
+            return Err(anyhow::anyhow!(
+                "HTTP status {}, expected {}",
+                status,
+                verify.expected_status
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check that a send tunnel's local target accepts connections
+    async fn local_service_up(tunnel: &Tunnel) -> bool {
+        #[cfg(unix)]
+        if let Some(path) = tunnel.local_socket_path() {
+            return matches!(
+                time::timeout(
+                    Duration::from_secs(2),
+                    tokio::net::UnixStream::connect(path)
+                )
+                .await,
+                Ok(Ok(_))
+            );
+        }
+
+        matches!(
+            time::timeout(
+                Duration::from_secs(2),
+                TcpStream::connect((tunnel.local_host.as_str(), tunnel.local_port)),
+            )
+            .await,
+            Ok(Ok(_))
+        )
+    }
+
+    /// Remove a Unix socket file, leaving anything that is not a socket alone
+    fn remove_stale_socket(path: &str) {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+
+            if let Ok(metadata) = std::fs::symlink_metadata(path) {
+                if metadata.file_type().is_socket() {
+                    if let Err(e) = std::fs::remove_file(path) {
+                        warn!("Failed to remove socket {}: {}", path, e);
+                    }
+                }
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = path;
+    }
+}
+
+/// Keepalive interval for one tunnel: the configured interval plus a stable
+/// per-tunnel offset of up to 10%, so tunnels started together don't send
+/// their keepalives in lockstep
+fn keepalive_interval(tunnel_id: &str, base_secs: u64) -> u64 {
+    use std::hash::{Hash, Hasher};
+
+    let base_secs = base_secs.max(1);
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    tunnel_id.hash(&mut hasher);
+    base_secs + hasher.finish() % (base_secs / 10 + 1)
+}
+
+/// Set when ssh runs m-tunnel itself as its askpass program
+const ASKPASS_VAR: &str = "M_TUNNEL_ASKPASS";
+
+/// Carries the key passphrase to the askpass invocation, never on a command line
+const ASKPASS_PASSPHRASE_VAR: &str = "M_TUNNEL_KEY_PASSPHRASE";
+
+/// When ssh runs this process as its askpass program, print the key
+/// passphrase and return whether one was given; `None` in a normal run. Only
+/// ssh's passphrase prompt is answered, so a password prompt from the gate
+/// never receives the passphrase. Programs embedding m-tunnel with gates
+/// that set key_passphrase must call this first thing in `main`.
+pub fn answer_askpass() -> Option<bool> {
+    std::env::var_os(ASKPASS_VAR)?;
+    let prompt = std::env::args().nth(1).unwrap_or_default();
+    match std::env::var(ASKPASS_PASSPHRASE_VAR) {
+        Ok(passphrase) if prompt.starts_with("Enter passphrase for") => {
+            println!("{}", passphrase);
+            Some(true)
+        }
+        _ => Some(false),
+    }
+}
+
+/// Environment that makes ssh read an encrypted key's passphrase from this
+/// binary run as SSH_ASKPASS. Empty when the gate has no passphrase, leaving
+/// ssh to use its agent.
+fn askpass_env(gate: &SshConfig) -> Vec<(&'static str, String)> {
+    let (Some(passphrase), Ok(exe)) = (gate.key_passphrase(), std::env::current_exe()) else {
+        return Vec::new();
+    };
+    vec![
+        ("SSH_ASKPASS", exe.to_string_lossy().into_owned()),
+        ("SSH_ASKPASS_REQUIRE", "force".to_string()),
+        (ASKPASS_VAR, "1".to_string()),
+        (ASKPASS_PASSPHRASE_VAR, passphrase),
+    ]
+}
+
+/// Resolve a gate's host for pin_gate_ip. Falls back to resolving on every
+/// connect, with a warning, when the lookup fails.
+async fn resolve_gate(gate: &SshConfig) -> Option<IpAddr> {
+    match tokio::net::lookup_host((gate.host.as_str(), gate.port)).await {
+        Ok(mut addrs) => match addrs.next() {
+            Some(addr) => {
+                info!("Gate {} pinned to {}", gate.label(), addr.ip());
+                Some(addr.ip())
+            }
+            None => {
+                warn!(
+                    "Gate {} resolved to no addresses, not pinning",
+                    gate.label()
+                );
+                None
+            }
+        },
+        Err(e) => {
+            warn!(
+                "Failed to resolve gate {}, not pinning: {}",
+                gate.label(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Refuse to start with a gate certificate that has expired, is not yet
+/// valid or was issued for a different key than key_path
+async fn check_certificate(gate: &SshConfig, cert: &std::path::Path) -> Result<()> {
+    let details = ssh_keygen(&["-L", "-f"], cert).await?;
+
+    // "Public key: ED25519-CERT SHA256:..."
+    let cert_fingerprint = details
+        .lines()
+        .find_map(|l| l.trim().strip_prefix("Public key: "))
+        .and_then(|l| l.split_whitespace().nth(1))
+        .with_context(|| format!("{} is not an SSH certificate", cert.display()))?;
+
+    if !gate.key_path.as_os_str().is_empty() {
+        // "256 SHA256:... comment (ED25519)"
+        let key = ssh_keygen(&["-l", "-f"], &gate.key_path).await?;
+        if key.split_whitespace().nth(1) != Some(cert_fingerprint) {
+            anyhow::bail!(
+                "Certificate {} was not issued for key {}",
+                cert.display(),
+                gate.key_path.display()
+            );
+        }
+    }
+
+    // "Valid: from 2024-01-01T00:00:00 to 2024-01-02T00:00:00", also
+    // "Valid: forever", "Valid: after ..." and "Valid: before ...", in local time
+    let validity = details
+        .lines()
+        .find_map(|l| l.trim().strip_prefix("Valid: "))
+        .unwrap_or("forever");
+    let parse = |s: &str| chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S").ok();
+    let words: Vec<&str> = validity.split_whitespace().collect();
+    let (not_before, not_after) = match words.as_slice() {
+        ["from", from, "to", to] => (parse(from), parse(to)),
+        ["after", from] => (parse(from), None),
+        ["before", to] => (None, parse(to)),
+        _ => (None, None),
+    };
+
+    let now = chrono::Local::now().naive_local();
+    if not_after.is_some_and(|t| t <= now) {
+        anyhow::bail!("Certificate {} has expired ({})", cert.display(), validity);
+    }
+    if not_before.is_some_and(|t| t > now) {
+        anyhow::bail!(
+            "Certificate {} is not valid yet ({})",
+            cert.display(),
+            validity
+        );
+    }
+
+    info!(
+        "Gate {} authenticates with certificate {} (valid {})",
+        gate.label(),
+        cert.display(),
+        validity
+    );
+    Ok(())
+}
+
+/// Fetch the gate's host keys with ssh-keyscan and write the one matching
+/// host_key_fingerprint to a known_hosts file of its own, which ssh then
+/// checks strictly. Refuses to start if the gate presents no matching key.
+async fn pin_host_key(gate: &SshConfig, fingerprint: &str) -> Result<PathBuf> {
+    // Numbered per process, so gates pinned on reload never reuse a live file
+    static PINNED: AtomicUsize = AtomicUsize::new(0);
+
+    let target = gate
+        .pinned_ip
+        .map(|ip| ip.to_string())
+        .unwrap_or_else(|| gate.host.clone());
+    let output = Command::new("ssh-keyscan")
+        .args(["-T", &gate.timeout.max(1).to_string(), "-p"])
+        .arg(gate.port.to_string())
+        .arg(&target)
+        .output()
+        .await
+        .context("Failed to run ssh-keyscan")?;
+    let scanned = String::from_utf8_lossy(&output.stdout);
+
+    let mut presented = Vec::new();
+    for line in scanned.lines().filter(|l| !l.starts_with('#')) {
+        // "host keytype base64-key"
+        let Some((_, key)) = line.split_once(' ') else {
+            continue;
+        };
+        let Ok(found) = key_fingerprint(key).await else {
+            continue;
+        };
+        if found != fingerprint {
+            presented.push(found);
+            continue;
+        }
+
+        // Listed under the name with and without the port, so it is found
+        // both for a direct connect and under pin_gate_ip's HostKeyAlias
+        let entry = format!("{},[{}]:{} {}\n", gate.host, gate.host, gate.port, key);
+        let path = std::env::temp_dir().join(format!(
+            "m-tunnel-{}-gate{}.known_hosts",
+            std::process::id(),
+            PINNED.fetch_add(1, Ordering::Relaxed)
+        ));
+        write_new_file(&path, entry.as_bytes())
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        info!("Gate {} host key pinned to {}", gate.label(), fingerprint);
+        return Ok(path);
+    }
+
+    if presented.is_empty() {
+        anyhow::bail!(
+            "Gate {} did not present any host key to ssh-keyscan: {}",
+            gate.label(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    anyhow::bail!(
+        "Gate {} presented no host key matching host_key_fingerprint {} (got {})",
+        gate.label(),
+        fingerprint,
+        presented.join(", ")
+    )
+}
+
+/// SHA256 fingerprint of a public key given as "keytype base64-key"
+async fn key_fingerprint(key: &str) -> Result<String> {
+    let mut child = Command::new("ssh-keygen")
+        .args(["-l", "-E", "sha256", "-f", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("Failed to run ssh-keygen")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(format!("{}\n", key).as_bytes()).await?;
+    }
+    let output = child.wait_with_output().await?;
+
+    // "256 SHA256:... no comment (ED25519)"
+    String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .nth(1)
+        .map(str::to_string)
+        .context("ssh-keygen could not read the host key")
+}
+
+/// Delete the known_hosts files `pin_host_key` wrote for these gates
+fn remove_pinned_known_hosts(gates: &[SshConfig]) {
+    for path in gates.iter().filter_map(|g| g.pinned_known_hosts.as_deref()) {
+        if let Err(e) = std::fs::remove_file(path) {
+            warn!("Failed to remove {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Create a file, replacing whatever was at the path without following it
+fn write_new_file(path: &std::path::Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o644);
+    options.open(path)?.write_all(contents)
+}
+
+async fn ssh_keygen(args: &[&str], path: &std::path::Path) -> Result<String> {
+    let output = Command::new("ssh-keygen")
+        .args(args)
+        .arg(path)
+        .output()
+        .await
+        .context("Failed to run ssh-keygen")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "ssh-keygen could not read {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Make sure a gate's known_hosts file exists and is writable, so ssh can
+/// record the host key on first connect instead of failing later. Under the
+/// strict policy nothing is recorded, so the file must already exist.
+fn prepare_known_hosts(path: &std::path::Path, policy: HostKeyPolicy) -> Result<()> {
+    if policy == HostKeyPolicy::Strict {
+        if !path.is_file() {
+            return Err(anyhow::anyhow!(
+                "known_hosts file {} does not exist; host_key_policy strict needs the gate's key listed in it",
+                path.display()
+            ));
+        }
+        return Ok(());
+    }
+
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        if !dir.exists() {
+            let mut builder = std::fs::DirBuilder::new();
+            builder.recursive(true);
+            #[cfg(unix)]
+            std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o755);
+            builder.create(dir).with_context(|| {
+                format!("Failed to create known_hosts directory {}", dir.display())
+            })?;
+            info!("Created known_hosts directory {}", dir.display());
+        }
+    }
+
+    let mut options = std::fs::OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o644);
+    options
+        .open(path)
+        .with_context(|| format!("known_hosts file {} is not writable", path.display()))?;
+
+    Ok(())
+}
+
+/// Check whether an ssh stderr line is one of ssh's own messages rather than
+/// text sent by the gate
+fn is_ssh_diagnostic(line: &str) -> bool {
+    const PREFIXES: &[&str] = &[
+        "Warning:",
+        "ssh:",
+        "channel ",
+        "connect_to ",
+        "Connection ",
+        "Authenticated ",
+        "Load key ",
+        "Bad ",
+        "Host key ",
+        "kex_",
+        "debug",
+        "bind ",
+        "unix_listener:",
+    ];
+    PREFIXES.iter().any(|p| line.starts_with(p))
+        || is_auth_rejection(line)
+        || is_forward_rejection(line)
+}
+
+/// Check whether an ssh stderr line reports the gate rejecting our credentials,
+/// e.g. "user@gate: Permission denied (publickey,password)." A local bind or
+/// socket error also says "Permission denied", but without the method list.
+fn is_auth_rejection(line: &str) -> bool {
+    line.contains("Permission denied (")
+}
+
+/// Check whether an ssh stderr line reports the gate refusing a forwarded channel
+fn is_forward_rejection(line: &str) -> bool {
+    line.contains("administratively prohibited")
 }